@@ -9,7 +9,7 @@ export type FileData = {
     selected_extension: string
     isSupported: boolean,
     convertibles: string[]
-    conversionStatus: "idle" | "queued" | "processing" | "failed" | "success" | "cancelled"
+    conversionStatus: "idle" | "queued" | "processing" | "failed" | "success" | "cancelled" | "skipped"
     progress?: number
 }
 
@@ -1,18 +1,54 @@
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 
-type Settings = {
-    conversion_mode: string,
+export type ConversionMode = "normal" | "hwaccel" | "lossless"
+
+export type VideoEncoder = "libx264" | "libx265" | "libvpx-vp9" | "libsvtav1"
+
+export type CollisionPolicy = "auto_suffix" | "skip" | "overwrite" | "ask"
+
+export type FfmpegLogLevel = "error" | "warning" | "info" | "verbose" | "debug"
+
+export type OutputLocation =
+    | { kind: "source" }
+    | { kind: "folder", path: string }
+    | { kind: "subfolder", name: string }
+    | { kind: "mirror", source_root: string, target_root: string }
+
+export type OutputRule = {
+    location: OutputLocation,
+    // File name without the extension, e.g. "{stem}_{width}p"
+    template: string,
+}
+
+// Mirrors Settings in settings_helper.rs
+export type Settings = {
+    schema_version: number,
+    conversion_mode: ConversionMode,
     max_concurrency: number,
     open_when_finished: boolean,
-    default_encoder: string,
+    default_encoder: VideoEncoder,
+    force_reencode: boolean,
+    collision_policy: CollisionPolicy,
+    output: OutputRule,
+    ffmpeg_log_level: FfmpegLogLevel,
+    // null uses the downloaded FFmpeg
+    ffmpeg_path: string | null,
 }
 
 export const useSettings = () => {
+    // Replaced by the saved settings in loadSettings
     const settings = ref<Settings>({
-        conversion_mode: "",
+        schema_version: 2,
+        conversion_mode: "normal",
         max_concurrency: 1,
         open_when_finished: true,
-        default_encoder: "libx264"
+        default_encoder: "libx265",
+        force_reencode: false,
+        collision_policy: "auto_suffix",
+        output: { location: { kind: "source" }, template: "{stem}" },
+        ffmpeg_log_level: "warning",
+        ffmpeg_path: null,
     });
 
     /* const saveSettings = async () => {
@@ -20,13 +56,43 @@ export const useSettings = () => {
         await invoke("save_settings", { newSettings: settings.value });
     } */
 
+    // Error of the last save, the settings are reloaded then so they show what is actually saved
+    const saveError = ref("");
+
+    // JSON of the settings as they are saved, the watcher below doesn't save them again
+    let saved = "";
+
+    const applySaved = (savedSettings: Settings) => {
+        saved = JSON.stringify(savedSettings);
+        settings.value = savedSettings;
+    }
+
     const loadSettings = async () => {
-        settings.value = await invoke<Settings>("load_settings");
+        applySaved(await invoke<Settings>("load_settings"));
     }
 
+    // Saved from another window or edited outside the app
+    const unlisten = listen<Settings>("settings-changed", (event) => {
+        if(JSON.stringify(event.payload) !== JSON.stringify(settings.value)) applySaved(event.payload);
+    });
+
+    // The listener belongs to the component using the settings, like the watcher below
+    onUnmounted(() => {
+        unlisten.then((stop) => stop());
+    });
+
     watch(settings, async (changedSettings) => {
-        if(changedSettings) await invoke("save_settings", { newSettings: changedSettings });
+        if(!changedSettings || JSON.stringify(changedSettings) === saved) return;
+
+        try {
+            await invoke("save_settings", { newSettings: changedSettings });
+            saved = JSON.stringify(changedSettings);
+            saveError.value = "";
+        } catch (e) {
+            saveError.value = typeof e === "object" && e ? Object.values(e).join(" ") : String(e);
+            await loadSettings();
+        }
     }, { deep: true });
 
-    return { settings, loadSettings };
+    return { settings, loadSettings, saveError };
 }
\ No newline at end of file
@@ -0,0 +1,484 @@
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    helpers::{
+        ffmpeg_helper::ConversionOptions,
+        file_helper::{file_category, file_details},
+        history_helper::record_job,
+        output_helper::{CollisionPolicy, OutputLocation, OutputRule},
+        preset_helper::{find_preset, list_presets},
+        probe_helper::{probe, MediaInfo},
+        queue_helper::{settle_output, JobPriority, JobRequest, PipelineJob},
+        settings_helper::load_settings,
+    },
+    tools::{
+        convert::{exec_conversion, ConversionReport},
+        engine::EventSink,
+        error::Error,
+        installer::is_ffmpeg_installed,
+        job_control::JobControl,
+    },
+};
+
+// Exit code for a wrong command line, every other failure uses Error::exit_code
+const USAGE_ERROR: i32 = 2;
+
+const COMMANDS: [&str; 6] = ["convert", "probe", "presets", "help", "--help", "--version"];
+
+const HELP: &str = "Usage: draconv <command> [options]
+
+Commands:
+  convert <input>... [options]   Convert one or more files
+      --to <extension>           Output format, not needed with --preset
+      --preset <name>            Conversion preset
+      --out <folder>             Output folder, next to the input by default
+      --name <template>          File name template, e.g. \"{stem}_{width}x{height}\"
+      --overwrite | --skip | --suffix
+                                 What to do if the output already exists
+      --video-codec <encoder>    --audio-codec <encoder>
+      --crf <0-51>               --video-bitrate <rate>   --audio-bitrate <rate>
+      --width <px>               --height <px>            --fps <rate>
+      --sample-rate <hz>
+      --remux                    Copy the streams even if the format doesn't change
+  probe <file> [--json]          Show the streams and format of a file
+  presets list [--json]          List the available presets
+  help                           Show this message
+
+Options:
+  --portable                     Keep the settings and FFmpeg in a data folder next to the executable
+
+Running draconv without a command, or with files only, opens the app.";
+
+// The app is only started headless for the commands above, so \"draconv clip.mp4\" still opens the window
+pub fn is_cli_command(args: &[String]) -> bool {
+    args.get(1)
+        .is_some_and(|arg| COMMANDS.contains(&arg.as_str()))
+}
+
+struct ConvertArgs {
+    inputs: Vec<String>,
+    to: Option<String>,
+    preset: Option<String>,
+    out: Option<String>,
+    name: Option<String>,
+    collision: Option<CollisionPolicy>,
+    options: ConversionOptions,
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: String) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
+
+fn parse_convert(args: &[String]) -> Result<ConvertArgs, String> {
+    let mut parsed = ConvertArgs {
+        inputs: vec![],
+        to: None,
+        preset: None,
+        out: None,
+        name: None,
+        collision: None,
+        options: ConversionOptions::default(),
+    };
+
+    let mut args = args.iter().cloned();
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            parsed.inputs.push(arg);
+            continue;
+        }
+
+        match arg.as_str() {
+            "--overwrite" => parsed.collision = Some(CollisionPolicy::Overwrite),
+            "--skip" => parsed.collision = Some(CollisionPolicy::Skip),
+            "--suffix" => parsed.collision = Some(CollisionPolicy::AutoSuffix),
+            "--remux" => parsed.options.remux = true,
+            flag => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("Missing value for {}", flag))?;
+                let options = &mut parsed.options;
+
+                match flag {
+                    "--to" => parsed.to = Some(value.trim_start_matches('.').to_lowercase()),
+                    "--preset" => parsed.preset = Some(value),
+                    "--out" => parsed.out = Some(value),
+                    "--name" => parsed.name = Some(value),
+                    "--video-codec" => options.video_codec = Some(value),
+                    "--audio-codec" => options.audio_codec = Some(value),
+                    "--video-bitrate" => options.video_bitrate = Some(value),
+                    "--audio-bitrate" => options.audio_bitrate = Some(value),
+                    "--crf" => options.crf = Some(parse_number(flag, value)?),
+                    "--width" => options.width = Some(parse_number(flag, value)?),
+                    "--height" => options.height = Some(parse_number(flag, value)?),
+                    "--fps" => options.frame_rate = Some(parse_number(flag, value)?),
+                    "--sample-rate" => options.sample_rate = Some(parse_number(flag, value)?),
+                    _ => return Err(format!("Unknown option: {}", flag)),
+                }
+            }
+        }
+    }
+
+    if parsed.inputs.is_empty() {
+        return Err("No input files given".into());
+    }
+
+    if parsed.to.is_none() && parsed.preset.is_none() {
+        return Err("Either --to or --preset is required".into());
+    }
+
+    Ok(parsed)
+}
+
+fn format_seconds(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+// Progress events are printed on a single line that is rewritten on every update
+fn print_progress(progress: &Value) {
+    let mut line = format!("\r  {:>3}%", progress["progress"].as_i64().unwrap_or(0));
+
+    if let Some(speed) = progress["speed"].as_f64() {
+        line.push_str(&format!("  {:.2}x", speed));
+    }
+
+    if let Some(fps) = progress["fps"].as_f64() {
+        line.push_str(&format!("  {:.0} fps", fps));
+    }
+
+    if let Some(eta) = progress["eta"].as_f64() {
+        line.push_str(&format!("  ETA {}", format_seconds(eta)));
+    }
+
+    let mut stderr = io::stderr();
+    let _ = write!(stderr, "{:<60}", line);
+    let _ = stderr.flush();
+}
+
+// Engine events of the command line, only the progress is shown, the rest is printed by the commands
+pub struct ConsoleEvents;
+
+impl EventSink for ConsoleEvents {
+    fn emit(&self, event: &str, payload: Value) {
+        if event == "job-progress" {
+            print_progress(&payload);
+        }
+    }
+}
+
+async fn convert(args: &[String]) -> i32 {
+    let args = match parse_convert(args) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, HELP);
+            return USAGE_ERROR;
+        }
+    };
+
+    if !is_ffmpeg_installed() {
+        let error = Error::FfmpegNotInstalled;
+        eprintln!("{}", error);
+        return error.exit_code();
+    }
+
+    let settings = load_settings();
+
+    let extension = match &args.preset {
+        Some(name) => match find_preset(name) {
+            Some(preset) => preset.container,
+            None => {
+                let error = Error::PresetNotFound(name.clone());
+                eprintln!("{}", error);
+                return error.exit_code();
+            }
+        },
+        None => args.to.clone().unwrap_or_default(),
+    };
+
+    // Output folders from the command line are relative to the working directory
+    let location = match &args.out {
+        Some(out) => {
+            let out = std::env::current_dir()
+                .map(|dir| dir.join(out))
+                .unwrap_or_else(|_| PathBuf::from(out));
+
+            OutputLocation::Folder {
+                path: out.to_string_lossy().to_string(),
+            }
+        }
+        None => settings.output.location.clone(),
+    };
+
+    let rule = OutputRule {
+        location,
+        template: args
+            .name
+            .clone()
+            .unwrap_or(settings.output.template.clone()),
+    };
+
+    let mut reserved = Vec::new();
+    let mut exit_code = 0;
+
+    for (index, input) in args.inputs.iter().enumerate() {
+        let result = convert_file(input, &extension, &args, &rule, index + 1, &mut reserved).await;
+
+        match result {
+            Ok(Some(output_path)) => eprintln!("\r{:<60}\r{} -> {}", "", input, output_path),
+            Ok(None) => eprintln!("{} skipped, the output already exists", input),
+            Err(Error::ConversionCancelled) => {
+                eprintln!("\nCancelled");
+                return Error::ConversionCancelled.exit_code();
+            }
+            Err(e) => {
+                eprintln!("\r{:<60}\r{}: {}", "", input, e);
+                exit_code = e.exit_code();
+            }
+        }
+    }
+
+    exit_code
+}
+
+// Runs a single file through the same job pipeline as the queue. Returns the output path, or None if skipped.
+async fn convert_file(
+    input: &str,
+    extension: &str,
+    args: &ConvertArgs,
+    rule: &OutputRule,
+    index: usize,
+    reserved: &mut Vec<PathBuf>,
+) -> Result<Option<String>, Error> {
+    let input = std::env::current_dir()
+        .map(|dir| dir.join(input))
+        .unwrap_or_else(|_| PathBuf::from(input))
+        .to_string_lossy()
+        .to_string();
+
+    let details = file_details(&input)?;
+
+    let category = file_category(&details.file_extension)
+        .ok_or_else(|| Error::InvalidCategory(details.file_extension.clone()))?;
+
+    let request = JobRequest {
+        id: Uuid::new_v4().to_string(),
+        path: input,
+        extension: extension.to_string(),
+        category: category.to_string(),
+        priority: JobPriority::default(),
+        preset: args.preset.clone(),
+        options: args.options.clone(),
+        collision: args.collision,
+        output: Some(rule.clone()),
+    };
+
+    let mut job = PipelineJob::from_request(request)?;
+    job.apply_output_rule(rule, index).await?;
+
+    let policy = load_settings().collision_policy;
+
+    // There is nobody to ask on the command line, so "ask" fails with OutputExists
+    if !settle_output(&mut job, policy, reserved)? {
+        return Ok(None);
+    }
+
+    let control = Arc::new(JobControl::new());
+
+    let mut report = ConversionReport::default();
+
+    let result = {
+        let conversion = exec_conversion(&job, &control, &mut report);
+        tokio::pin!(conversion);
+
+        tokio::select! {
+            result = &mut conversion => result,
+            _ = tokio::signal::ctrl_c() => {
+                // The conversion kills FFmpeg and removes its temp file once it sees the cancel request
+                control.cancel();
+                conversion.await
+            }
+        }
+    };
+
+    record_job(&job, &report, &result);
+
+    result?;
+
+    Ok(Some(job.output_path))
+}
+
+fn print_media(path: &str, media: &MediaInfo) {
+    println!("{}", path);
+
+    if let Some(container) = &media.container {
+        println!("  Container: {}", container);
+    }
+
+    if let Some(duration) = media.duration {
+        println!("  Duration:  {}", format_seconds(duration));
+    }
+
+    if let Some(size) = media.size {
+        println!("  Size:      {:.2} MB", size as f64 / 1_000_000.0);
+    }
+
+    if let Some(bit_rate) = media.bit_rate {
+        println!("  Bitrate:   {} kb/s", bit_rate / 1000);
+    }
+
+    for stream in &media.streams {
+        let mut line = format!(
+            "  Stream #{}: {:?} {}",
+            stream.index,
+            stream.kind,
+            stream.codec.as_deref().unwrap_or("unknown")
+        );
+
+        if let (Some(width), Some(height)) = (stream.width, stream.height) {
+            line.push_str(&format!(", {}x{}", width, height));
+        }
+
+        if let Some(frame_rate) = stream.frame_rate {
+            line.push_str(&format!(", {:.2} fps", frame_rate));
+        }
+
+        if let Some(channels) = stream.channels {
+            line.push_str(&format!(", {} channels", channels));
+        }
+
+        if let Some(sample_rate) = stream.sample_rate {
+            line.push_str(&format!(", {} Hz", sample_rate));
+        }
+
+        if let Some(language) = &stream.language {
+            line.push_str(&format!(" ({})", language));
+        }
+
+        println!("{}", line);
+    }
+
+    for chapter in &media.chapters {
+        println!(
+            "  Chapter: {} - {} {}",
+            format_seconds(chapter.start),
+            format_seconds(chapter.end),
+            chapter.title.as_deref().unwrap_or_default()
+        );
+    }
+}
+
+async fn probe_file(args: &[String]) -> i32 {
+    let json = args.iter().any(|arg| arg == "--json");
+
+    let Some(path) = args.iter().find(|arg| !arg.starts_with("--")) else {
+        eprintln!("No input file given\n\n{}", HELP);
+        return USAGE_ERROR;
+    };
+
+    if !is_ffmpeg_installed() {
+        let error = Error::FfmpegNotInstalled;
+        eprintln!("{}", error);
+        return error.exit_code();
+    }
+
+    match probe(path).await {
+        Ok(media) if json => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&media).unwrap_or_default()
+            );
+            0
+        }
+        Ok(media) => {
+            print_media(path, &media);
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            e.exit_code()
+        }
+    }
+}
+
+fn presets(args: &[String]) -> i32 {
+    if args.first().map(|s| s.as_str()) != Some("list") {
+        eprintln!("Unknown presets command\n\n{}", HELP);
+        return USAGE_ERROR;
+    }
+
+    let presets = list_presets();
+
+    if args.iter().any(|arg| arg == "--json") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&presets).unwrap_or_default()
+        );
+        return 0;
+    }
+
+    for preset in presets {
+        let video = if preset.disable_video {
+            "no video"
+        } else {
+            preset.video_codec.as_deref().unwrap_or("default encoder")
+        };
+
+        println!(
+            "{:<20} {:<6} {}, {}{}",
+            preset.name,
+            preset.container,
+            video,
+            preset.audio_codec,
+            if preset.builtin { "" } else { " (custom)" }
+        );
+    }
+
+    0
+}
+
+// Release builds use the windows subsystem, so the output has to go to the console of the parent process
+#[cfg(target_os = "windows")]
+pub fn attach_console() {
+    use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn attach_console() {}
+
+// Runs a command line command and returns the process exit code
+pub async fn run(args: Vec<String>) -> i32 {
+    let rest = args.get(2..).unwrap_or_default();
+
+    match args.get(1).map(|s| s.as_str()) {
+        Some("convert") => convert(rest).await,
+        Some("probe") => probe_file(rest).await,
+        Some("presets") => presets(rest),
+        Some("--version") => {
+            println!("draconv {}", env!("CARGO_PKG_VERSION"));
+            0
+        }
+        _ => {
+            println!("{}", HELP);
+            0
+        }
+    }
+}
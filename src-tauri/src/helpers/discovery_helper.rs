@@ -0,0 +1,230 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+use serde::Serialize;
+use tokio::{process::Command, time::timeout};
+
+use crate::{
+    helpers::{
+        ffmpeg_helper::{binary_name, bundled_ffmpeg_path, get_ffmpeg_path},
+        settings_helper::load_settings,
+    },
+    tools::{engine::engine, error::Error},
+};
+
+// A broken binary (e.g. missing libraries or the wrong architecture) may hang instead of failing
+const VERSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FfmpegOrigin {
+    // Downloaded by Draconv
+    Bundled,
+    // Chosen by the user
+    Custom,
+    // In a folder of PATH
+    Path,
+    // In a folder package managers install to
+    Common,
+}
+
+// An FFmpeg that was run successfully
+#[derive(Debug, Clone, Serialize)]
+pub struct FfmpegBuild {
+    pub path: String,
+    pub origin: FfmpegOrigin,
+    // e.g. "6.1.1-3ubuntu5" or "N-118896-g9f3d5a2b4c-20250301"
+    pub version: String,
+    // Options FFmpeg was built with, e.g. "--enable-vaapi"
+    pub configuration: Vec<String>,
+    // Without ffprobe next to it media information is read with ffmpeg, which knows less
+    pub has_ffprobe: bool,
+    // The one jobs use
+    pub selected: bool,
+}
+
+// Folders package managers install FFmpeg to. Apps started from the desktop (especially on macOS) don't
+// get the PATH of the shell, so these are searched even when they are not in PATH.
+fn common_dirs() -> Vec<PathBuf> {
+    let env_dir = |name: &str| env::var_os(name).map(PathBuf::from);
+
+    if cfg!(target_os = "windows") {
+        let mut dirs = vec![
+            PathBuf::from(r"C:\ffmpeg\bin"),
+            PathBuf::from(r"C:\ProgramData\chocolatey\bin"),
+        ];
+
+        dirs.extend(env_dir("ProgramFiles").map(|dir| dir.join(r"ffmpeg\bin")));
+        dirs.extend(env_dir("LOCALAPPDATA").map(|dir| dir.join(r"Microsoft\WinGet\Links")));
+        dirs.extend(env_dir("USERPROFILE").map(|dir| dir.join(r"scoop\shims")));
+
+        dirs
+    } else if cfg!(target_os = "macos") {
+        vec![
+            PathBuf::from("/opt/homebrew/bin"),
+            PathBuf::from("/usr/local/bin"),
+            PathBuf::from("/opt/local/bin"),
+        ]
+    } else {
+        vec![
+            PathBuf::from("/usr/bin"),
+            PathBuf::from("/usr/local/bin"),
+            PathBuf::from("/snap/bin"),
+        ]
+    }
+}
+
+// Every place an FFmpeg may be, in the order they are listed. Most of them don't exist.
+fn candidates() -> Vec<(PathBuf, FfmpegOrigin)> {
+    let ffmpeg = binary_name("ffmpeg");
+    let mut list = Vec::new();
+
+    if let Ok(path) = bundled_ffmpeg_path() {
+        list.push((path, FfmpegOrigin::Bundled));
+    }
+
+    if let Some(path) = load_settings().ffmpeg_path {
+        list.push((PathBuf::from(path), FfmpegOrigin::Custom));
+    }
+
+    if let Some(paths) = env::var_os("PATH") {
+        list.extend(
+            env::split_paths(&paths)
+                .filter(|dir| dir.is_absolute())
+                .map(|dir| (dir.join(&ffmpeg), FfmpegOrigin::Path)),
+        );
+    }
+
+    list.extend(
+        common_dirs()
+            .into_iter()
+            .map(|dir| (dir.join(&ffmpeg), FfmpegOrigin::Common)),
+    );
+
+    list
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+// Runs `ffmpeg -version` and reads the version and build configuration from it
+pub async fn inspect_ffmpeg(path: &Path, origin: FfmpegOrigin) -> Result<FfmpegBuild, Error> {
+    let invalid = |reason: &str| Error::InvalidFfmpeg(format!("{} ({})", path.display(), reason));
+
+    if !path.is_absolute() {
+        return Err(invalid("the path must be absolute"));
+    }
+
+    if !path.is_file() {
+        return Err(invalid("file not found"));
+    }
+
+    let mut command = Command::new(path);
+
+    command
+        .arg("-version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+
+    #[cfg(target_os = "windows")]
+    #[allow(unused_imports)]
+    {
+        use std::os::windows::process::CommandExt;
+        command.creation_flags(0x08000000);
+    }
+
+    let output = timeout(VERSION_TIMEOUT, command.output())
+        .await
+        .map_err(|_| invalid("it did not answer"))?
+        .map_err(|e| invalid(&e.to_string()))?;
+
+    if !output.status.success() {
+        return Err(invalid("it failed to run"));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    let version = stdout
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("ffmpeg version "))
+        .and_then(|rest| rest.split_whitespace().next())
+        .ok_or_else(|| invalid("not an FFmpeg executable"))?;
+
+    let configuration = stdout
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("configuration:"))
+        .map(|options| options.split_whitespace().map(String::from).collect())
+        .unwrap_or_default();
+
+    Ok(FfmpegBuild {
+        path: path.to_string_lossy().to_string(),
+        origin,
+        version: version.to_string(),
+        configuration,
+        has_ffprobe: path.with_file_name(binary_name("ffprobe")).is_file(),
+        selected: get_ffmpeg_path().is_ok_and(|selected| same_file(&selected, path)),
+    })
+}
+
+// Every working FFmpeg on the machine. The same binary found twice (e.g. through a symlink) is listed once.
+#[tauri::command]
+pub async fn find_ffmpeg() -> Vec<FfmpegBuild> {
+    let mut found: Vec<FfmpegBuild> = Vec::new();
+
+    for (path, origin) in candidates() {
+        if !path.is_file()
+            || found
+                .iter()
+                .any(|build| same_file(Path::new(&build.path), &path))
+        {
+            continue;
+        }
+
+        match inspect_ffmpeg(&path, origin).await {
+            Ok(build) => found.push(build),
+            Err(e) => log::warn!("skipping FFmpeg: {}", e),
+        }
+    }
+
+    found
+}
+
+// The FFmpeg jobs use
+#[tauri::command]
+pub async fn get_ffmpeg_info() -> Result<FfmpegBuild, Error> {
+    let (path, origin) = match load_settings().ffmpeg_path {
+        Some(path) => (PathBuf::from(path), FfmpegOrigin::Custom),
+        None => (bundled_ffmpeg_path()?, FfmpegOrigin::Bundled),
+    };
+
+    if !path.exists() {
+        return Err(Error::FfmpegNotInstalled);
+    }
+
+    inspect_ffmpeg(&path, origin).await
+}
+
+// Makes jobs use the FFmpeg at `path`, or the downloaded one if it's None.
+// The binary is run first, one that doesn't work is never saved.
+#[tauri::command]
+pub async fn select_ffmpeg(path: Option<String>) -> Result<(), Error> {
+    if let Some(path) = &path {
+        inspect_ffmpeg(Path::new(path), FfmpegOrigin::Custom).await?;
+    }
+
+    let mut settings = load_settings();
+    settings.ffmpeg_path = path;
+
+    engine().settings().save(&settings)
+}
@@ -0,0 +1,245 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::helpers::ffmpeg_helper::hw_failure_reason;
+
+// Why FFmpeg failed, guessed from the errors it logged
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    UnsupportedCodec,
+    EncoderNotFound,
+    InvalidInput,
+    PermissionDenied,
+    DiskFull,
+    UnsupportedPixelFormat,
+    HardwareInit,
+    Unknown,
+}
+
+// Checked in order, the first kind with a matching pattern wins. Patterns are lowercase.
+// Hardware failures are only considered when a hardware encoder was used, and then between the two lists:
+// a missing encoder is reported as such even though "Unknown encoder" is a hardware failure pattern too,
+// and a hardware failure often ends in "Could not write header", which is a codec problem otherwise.
+const SYSTEM_PATTERNS: &[(FailureKind, &[&str])] = &[
+    (
+        FailureKind::DiskFull,
+        &["no space left on device", "disk quota exceeded"],
+    ),
+    (
+        FailureKind::PermissionDenied,
+        &[
+            "permission denied",
+            "operation not permitted",
+            "read-only file system",
+        ],
+    ),
+    (
+        FailureKind::EncoderNotFound,
+        &["unknown encoder", "encoder not found"],
+    ),
+];
+
+const MEDIA_PATTERNS: &[(FailureKind, &[&str])] = &[
+    (
+        FailureKind::UnsupportedPixelFormat,
+        &[
+            "pixel format not supported",
+            "incompatible pixel format",
+            "unsupported pixel format",
+            "is not supported by the encoder",
+            "not divisible by 2",
+        ],
+    ),
+    (
+        FailureKind::UnsupportedCodec,
+        &[
+            "could not find tag for codec",
+            "not currently supported in container",
+            "only vp8 or vp9 or av1 video",
+            "codec not supported",
+            "could not write header",
+        ],
+    ),
+    (
+        FailureKind::InvalidInput,
+        &[
+            "invalid data found when processing input",
+            "moov atom not found",
+            "could not find codec parameters",
+            "error while decoding",
+            "no such file or directory",
+        ],
+    ),
+];
+
+impl FailureKind {
+    // Stable code of the kind, also the exit code of the command line
+    pub fn code(&self) -> i32 {
+        match self {
+            FailureKind::Unknown => 4,
+            FailureKind::UnsupportedCodec => 20,
+            FailureKind::EncoderNotFound => 21,
+            FailureKind::InvalidInput => 22,
+            FailureKind::PermissionDenied => 23,
+            FailureKind::DiskFull => 24,
+            FailureKind::UnsupportedPixelFormat => 25,
+            FailureKind::HardwareInit => 26,
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            FailureKind::UnsupportedCodec => "The codec is not supported by the output format",
+            FailureKind::EncoderNotFound => "The encoder is not available",
+            FailureKind::InvalidInput => "The input file is damaged or not a media file",
+            FailureKind::PermissionDenied => "Permission denied",
+            FailureKind::DiskFull => "The disk is full",
+            FailureKind::UnsupportedPixelFormat => "The encoder doesn't support the pixel format",
+            FailureKind::HardwareInit => "The hardware encoder could not be started",
+            FailureKind::Unknown => "FFmpeg process failed",
+        }
+    }
+
+    pub fn remediation(&self) -> &'static str {
+        match self {
+            FailureKind::UnsupportedCodec => {
+                "Pick a different output format, or a codec that the format supports (e.g. H.264 or H.265 with AAC for mp4)."
+            }
+            FailureKind::EncoderNotFound => {
+                "Your FFmpeg build or GPU doesn't have this encoder. Choose another encoder or use CPU (Normal) encoding."
+            }
+            FailureKind::InvalidInput => {
+                "Check that the input file plays correctly. It may be incomplete, damaged or still being copied."
+            }
+            FailureKind::PermissionDenied => {
+                "Choose an output folder you can write to, or check the permissions of the input file."
+            }
+            FailureKind::DiskFull => {
+                "Free up space on the output drive or choose an output folder on another drive."
+            }
+            FailureKind::UnsupportedPixelFormat => {
+                "Choose another encoder, or resize the video to an even width and height."
+            }
+            FailureKind::HardwareInit => {
+                "Update your graphics driver, or use CPU (Normal) encoding in the settings."
+            }
+            FailureKind::Unknown => "Open the job log to see the full FFmpeg output.",
+        }
+    }
+}
+
+// A failed FFmpeg run, serialized so the frontend can show advice for the kind of failure
+#[derive(Debug, Clone, Serialize)]
+pub struct FfmpegFailure {
+    pub kind: FailureKind,
+    pub code: i32,
+    pub title: String,
+    pub remediation: String,
+    // The errors FFmpeg logged
+    pub message: String,
+}
+
+impl fmt::Display for FfmpegFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.message.is_empty() {
+            write!(f, "{}. {}", self.title, self.remediation)
+        } else {
+            write!(f, "{}. {}\n{}", self.title, self.remediation, self.message)
+        }
+    }
+}
+
+fn find_kind(list: &[(FailureKind, &[&str])], lowercase: &str) -> Option<FailureKind> {
+    list.iter()
+        .find(|(_, patterns)| patterns.iter().any(|p| lowercase.contains(p)))
+        .map(|(kind, _)| *kind)
+}
+
+// `hw_encoder` is true if the failed run used a hardware encoder
+pub fn classify_failure(message: String, hw_encoder: bool) -> FfmpegFailure {
+    let lowercase = message.to_lowercase();
+
+    let kind = find_kind(SYSTEM_PATTERNS, &lowercase)
+        .or_else(|| {
+            hw_failure_reason(&message)
+                .filter(|_| hw_encoder)
+                .map(|_| FailureKind::HardwareInit)
+        })
+        .or_else(|| find_kind(MEDIA_PATTERNS, &lowercase))
+        .unwrap_or(FailureKind::Unknown);
+
+    FfmpegFailure {
+        kind,
+        code: kind.code(),
+        title: kind.title().to_string(),
+        remediation: kind.remediation().to_string(),
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(message: &str, hw_encoder: bool) -> FailureKind {
+        classify_failure(message.to_string(), hw_encoder).kind
+    }
+
+    #[test]
+    fn classifies_media_errors() {
+        assert_eq!(
+            kind("input.mp4: Invalid data found when processing input", false),
+            FailureKind::InvalidInput
+        );
+        assert_eq!(
+            kind("Could not find tag for codec vp8 in stream #0", false),
+            FailureKind::UnsupportedCodec
+        );
+        assert_eq!(
+            kind("width not divisible by 2 (1921x1080)", false),
+            FailureKind::UnsupportedPixelFormat
+        );
+        assert_eq!(kind("Conversion failed!", false), FailureKind::Unknown);
+    }
+
+    #[test]
+    fn system_errors_win_over_media_errors() {
+        assert_eq!(
+            kind(
+                "Could not write header for output file: No space left on device",
+                false
+            ),
+            FailureKind::DiskFull
+        );
+        assert_eq!(
+            kind("out.mp4: Permission denied", false),
+            FailureKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn hardware_failures_need_a_hardware_encoder() {
+        let message = "No NVENC capable devices found\nCould not write header for output file";
+
+        assert_eq!(kind(message, true), FailureKind::HardwareInit);
+        assert_eq!(kind(message, false), FailureKind::UnsupportedCodec);
+    }
+
+    #[test]
+    fn missing_encoder_is_not_a_hardware_failure() {
+        assert_eq!(
+            kind("Unknown encoder 'h264_nvenc'", true),
+            FailureKind::EncoderNotFound
+        );
+    }
+
+    #[test]
+    fn failure_carries_code_and_message() {
+        let failure = classify_failure("moov atom not found".into(), false);
+
+        assert_eq!(failure.code, FailureKind::InvalidInput.code());
+        assert_eq!(failure.message, "moov atom not found");
+    }
+}
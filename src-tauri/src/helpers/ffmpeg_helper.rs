@@ -1,5 +1,18 @@
-use crate::helpers::{gpu_helper::select_best_encoder, settings_helper::load_settings};
-use std::{path::PathBuf, process::Command};
+use crate::{
+    helpers::{
+        gpu_helper::select_best_encoder,
+        preset_helper::Preset,
+        probe_helper::MediaInfo,
+        remux_helper::{plan_stream_copy, StreamCopy},
+        settings_helper::{load_settings, ConversionMode, Settings},
+    },
+    tools::{engine::data_path, error::Error},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 #[derive(Debug)]
 pub enum Encoder {
@@ -10,115 +23,103 @@ pub enum Encoder {
     APPLE,
 }
 
+// Per-job overrides, anything left empty falls back to the codec profile and settings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConversionOptions {
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub crf: Option<u8>,
+    // e.g. "4M", takes precedence over crf
+    pub video_bitrate: Option<String>,
+    // If only one side is given, the other one keeps the aspect ratio
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub frame_rate: Option<f64>,
+    // e.g. "192k"
+    pub audio_bitrate: Option<String>,
+    pub sample_rate: Option<u32>,
+    // Copies the streams that fit even when the container doesn't change, otherwise converting a file
+    // to its own container always re-encodes it with the preset
+    pub remux: bool,
+}
+
+impl ConversionOptions {
+    fn scale_filter(&self) -> Option<String> {
+        if self.width.is_none() && self.height.is_none() {
+            return None;
+        }
+
+        let side = |value: Option<u32>| value.map_or("-2".to_string(), |v| v.to_string());
+
+        Some(format!("scale={}:{}", side(self.width), side(self.height)))
+    }
+}
+
 struct CodecProfile {
     video: String,
-    audio: &'static str,
+    audio: String,
     disable_video: bool,
     hwaccel_supported: bool,
+    quality: Option<u8>,
     arguments: Vec<String>,
 }
 
-fn get_codec_profile(format: &str) -> CodecProfile {
-    let settings = load_settings();
-    let default_enc = settings.default_encoder;
-
-    match format {
-        "mp4" => CodecProfile {
-            video: default_enc,
-            audio: "aac",
-            disable_video: false,
-            hwaccel_supported: true,
-            arguments: vec![],
-        },
-        "webm" => CodecProfile {
-            video: "libvpx-vp9".into(),
-            audio: "libopus",
-            disable_video: false,
-            hwaccel_supported: false,
-            arguments: vec![],
-        },
-        "mov" => CodecProfile {
-            video: default_enc,
-            audio: "aac",
-            disable_video: false,
-            hwaccel_supported: true,
-            arguments: vec![],
-        },
-        "avi" => CodecProfile {
-            video: "mpeg4".into(),
-            audio: "mp3",
-            disable_video: false,
-            hwaccel_supported: false,
-            arguments: vec!["-quality".into(), "quality".into()],
-        },
-        "mkv" => CodecProfile {
-            video: default_enc,
-            audio: "libopus",
-            disable_video: false,
-            hwaccel_supported: true,
-            arguments: vec![],
-        },
-        "flv" => CodecProfile {
-            video: "flv".into(),
-            audio: "mp3",
-            disable_video: false,
-            hwaccel_supported: false,
-            arguments: vec![],
-        },
-        "wmv" => CodecProfile {
-            video: "msmpeg4".into(),
-            audio: "wmav2",
-            disable_video: false,
-            hwaccel_supported: false,
-            arguments: vec![],
+fn get_codec_profile(preset: Preset, settings: &Settings) -> CodecProfile {
+    CodecProfile {
+        video: if preset.disable_video {
+            "none".into()
+        } else {
+            preset
+                .video_codec
+                .unwrap_or_else(|| settings.default_encoder.as_str().to_string())
         },
+        audio: preset.audio_codec,
+        disable_video: preset.disable_video,
+        hwaccel_supported: preset.hwaccel_supported,
+        quality: preset.quality,
+        arguments: preset.extra_args,
+    }
+}
 
-        "mp3" => CodecProfile {
-            video: "none".into(),
-            audio: "libmp3lame",
-            disable_video: true,
-            hwaccel_supported: false,
-            arguments: vec![],
-        },
-        "aac" => CodecProfile {
-            video: "none".into(),
-            audio: "aac",
-            disable_video: true,
-            hwaccel_supported: false,
-            arguments: vec![],
-        },
-        "flac" => CodecProfile {
-            video: "none".into(),
-            audio: "flac",
-            disable_video: true,
-            hwaccel_supported: false,
-            arguments: vec![],
-        },
-        "wav" => CodecProfile {
-            video: "none".into(),
-            audio: "pcm_s16le",
-            disable_video: true,
-            hwaccel_supported: false,
-            arguments: vec![],
-        },
-        "ogg" => CodecProfile {
-            video: "none".into(),
-            audio: "libopus",
-            disable_video: true,
-            hwaccel_supported: false,
-            arguments: vec![],
-        },
+pub fn binary_name(name: &str) -> String {
+    if cfg!(target_os = "windows") {
+        format!("{}.exe", name)
+    } else {
+        name.to_string()
+    }
+}
 
-        _ => CodecProfile {
-            video: default_enc,
-            audio: "aac",
-            disable_video: false,
-            hwaccel_supported: true,
-            arguments: vec![],
-        },
+// The FFmpeg the installer downloads
+pub fn bundled_ffmpeg_path() -> Result<PathBuf, Error> {
+    get_bin_path("ffmpeg")
+}
+
+// The FFmpeg chosen in the settings, otherwise the downloaded one
+pub fn get_ffmpeg_path() -> Result<PathBuf, Error> {
+    match load_settings().ffmpeg_path {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => bundled_ffmpeg_path(),
+    }
+}
+
+// A chosen FFmpeg usually has its ffprobe next to it, the downloaded one is used if it doesn't
+pub fn get_ffprobe_path() -> Result<PathBuf, Error> {
+    let sibling = load_settings()
+        .ffmpeg_path
+        .map(|path| Path::new(&path).with_file_name(binary_name("ffprobe")))
+        .filter(|path| path.is_file());
+
+    match sibling {
+        Some(path) => Ok(path),
+        None => get_bin_path("ffprobe"),
     }
 }
 
+fn get_bin_path(name: &str) -> Result<PathBuf, Error> {
+    data_path(&format!("bin/{}", binary_name(name)))
+}
+
 pub fn time_to_seconds(time_str: &str) -> f64 {
     let parts: Vec<&str> = time_str.split(":").collect();
     if parts.len() != 3 {
@@ -132,11 +133,10 @@ pub fn time_to_seconds(time_str: &str) -> f64 {
 }
 
 pub fn get_video_duration_millis(ffmpeg_path: PathBuf, path: &str) -> f64 {
-    let duration_output = Command::new(&ffmpeg_path)
-        .args(&["-i", path])
-        .output()
-        .map_err(|e| e.to_string())
-        .unwrap();
+    let duration_output = match Command::new(&ffmpeg_path).args(["-i", path]).output() {
+        Ok(output) => output,
+        Err(_) => return 0.0,
+    };
 
     let duration_info = String::from_utf8_lossy(&duration_output.stderr);
 
@@ -151,15 +151,156 @@ pub fn get_video_duration_millis(ffmpeg_path: PathBuf, path: &str) -> f64 {
         .unwrap_or(0.0)
 }
 
-pub async fn ffmpeg_builder(input: &str, output_path: &str, output_format: &str) -> Vec<String> {
-    let profile = get_codec_profile(output_format);
-    let settings = load_settings();
+// FFmpeg error lines that mean the hardware encoder, decoder or device could not be initialized,
+// paired with the reason reported to the frontend when the job is retried on the CPU.
+// A match turns the encoder off for the whole session, so only messages printed by the hardware code of
+// each vendor belong here. Generic ones like "Error while opening encoder" are also printed for wrong
+// parameters on any encoder, and the CPU retry would fail the same way.
+const HW_FAILURE_PATTERNS: &[(&str, &str)] = &[
+    (
+        "Encoder not found",
+        "The hardware encoder is not available in this FFmpeg build",
+    ),
+    (
+        "Unknown encoder",
+        "The hardware encoder is not available in this FFmpeg build",
+    ),
+    (
+        "No NVENC capable devices found",
+        "No NVENC capable GPU was found",
+    ),
+    (
+        "OpenEncodeSessionEx failed",
+        "The NVENC encoder session could not be opened",
+    ),
+    (
+        "Driver does not support the required nvenc API version",
+        "The NVIDIA driver is too old for this FFmpeg build",
+    ),
+    ("CUDA_ERROR_NO_DEVICE", "No CUDA capable GPU was found"),
+    (
+        "Cannot load nvcuda.dll",
+        "The NVIDIA driver could not be loaded",
+    ),
+    (
+        "Cannot load libcuda",
+        "The NVIDIA driver could not be loaded",
+    ),
+    ("amfrt64.dll", "The AMD AMF runtime could not be loaded"),
+    ("amfrt32.dll", "The AMD AMF runtime could not be loaded"),
+    (
+        "AMF failed to initialise",
+        "The AMD AMF encoder could not be initialized",
+    ),
+    (
+        "Error creating a MFX session",
+        "The Intel Quick Sync session could not be created",
+    ),
+    (
+        "Error initializing an MFX session",
+        "The Intel Quick Sync session could not be created",
+    ),
+    (
+        "Error initializing an internal MFX session",
+        "The Intel Quick Sync session could not be created",
+    ),
+    (
+        "Device creation failed",
+        "The hardware device could not be created",
+    ),
+    (
+        "Failed to create Direct3D device",
+        "The hardware device could not be created",
+    ),
+    (
+        "Failed to initialise VAAPI connection",
+        "The VAAPI device could not be opened",
+    ),
+    (
+        "No VA display found",
+        "The VAAPI device could not be opened",
+    ),
+    (
+        "hwaccel initialisation returned error",
+        "The hardware decoder could not be initialized",
+    ),
+    (
+        "cannot create compression session",
+        "The VideoToolbox encoder session could not be created",
+    ),
+];
 
-    let lossless = settings.conversion_mode == "lossless";
+// Returns why a hardware accelerated run failed, or None if the failure is not hardware related
+pub fn hw_failure_reason(stderr: &str) -> Option<&'static str> {
+    HW_FAILURE_PATTERNS
+        .iter()
+        .find(|(pattern, _)| stderr.contains(pattern))
+        .map(|(_, reason)| *reason)
+}
+
+pub struct FfmpegArgs {
+    pub args: Vec<String>,
+    // Name of the hardware encoder if hardware acceleration is used
+    pub hw_encoder: Option<String>,
+    pub stream_copy: StreamCopy,
+}
+
+impl FfmpegArgs {
+    // Encoder given to a codec flag, e.g. "-c:v" -> "libx265" or "copy"
+    pub fn codec(&self, flag: &str) -> Option<String> {
+        self.args
+            .iter()
+            .position(|arg| arg == flag)
+            .and_then(|pos| self.args.get(pos + 1))
+            .cloned()
+    }
+}
+
+// `allow_hw` is false when retrying a job on the CPU after a hardware failure,
+// `allow_copy` is false when retrying after copying the streams failed.
+// `settings` is the snapshot the job took when it started, saving the settings mid-job doesn't change its arguments.
+#[allow(clippy::too_many_arguments)]
+pub async fn ffmpeg_builder(
+    input: &str,
+    output_path: &str,
+    preset: Preset,
+    options: &ConversionOptions,
+    media: &MediaInfo,
+    allow_hw: bool,
+    allow_copy: bool,
+    settings: &Settings,
+) -> FfmpegArgs {
+    let output_format = preset.container.clone();
+    let output_format = output_format.as_str();
+
+    let stream_copy = if allow_copy && !settings.force_reencode {
+        plan_stream_copy(input, output_format, media, &preset, options)
+    } else {
+        StreamCopy::default()
+    };
 
-    let (gpu_type, hw_accel_encoder, encoder_params, hw_accel_method) = select_best_encoder().await;
+    let mut profile = get_codec_profile(preset, settings);
 
-    let use_hw = !profile.disable_video
+    // An input without a video stream (e.g. an mp3 converted to mp4) can't be video encoded
+    if media.streams_known() && !media.has_video() {
+        profile.disable_video = true;
+    }
+
+    let lossless = settings.conversion_mode == ConversionMode::Lossless;
+
+    let video_codec = options
+        .video_codec
+        .clone()
+        .unwrap_or_else(|| profile.video.clone());
+
+    let (gpu_type, hw_accel_encoder, mut encoder_params, hw_accel_method) =
+        select_best_encoder(&video_codec, settings.conversion_mode).await;
+
+    let scale_filter = options.scale_filter();
+
+    let use_hw = allow_hw
+        && !stream_copy.video
+        && !profile.disable_video
         && profile.hwaccel_supported
         && matches!(gpu_type, Encoder::NVENC | Encoder::AMF | Encoder::QSV);
 
@@ -169,20 +310,21 @@ pub async fn ffmpeg_builder(input: &str, output_path: &str, output_format: &str)
         "pipe:1".to_string(),
         "-nostats".to_string(),
         "-loglevel".to_string(),
-        "error".to_string(),
+        settings.ffmpeg_log_level.as_arg().to_string(),
     ];
 
     if use_hw && hw_accel_method != "none" {
         cmd.push("-hwaccel".to_string());
         cmd.push(hw_accel_method.clone());
 
-        // Intel and Nvidia hwaccel methods need specific output formats
-        if hw_accel_method == "cuda" {
+        // Intel and Nvidia hwaccel methods need specific output formats.
+        // Frames are kept in system memory when a software scale filter has to run on them.
+        if hw_accel_method == "cuda" && scale_filter.is_none() {
             cmd.push("-hwaccel_output_format".to_string());
             cmd.push("cuda".to_string());
         }
 
-        if hw_accel_method == "qsv" {
+        if hw_accel_method == "qsv" && scale_filter.is_none() {
             cmd.push("-hwaccel_output_format".to_string());
             cmd.push("qsv".to_string());
         }
@@ -191,18 +333,36 @@ pub async fn ffmpeg_builder(input: &str, output_path: &str, output_format: &str)
     cmd.push("-i".to_string());
     cmd.push(input.to_string());
 
-    if !profile.disable_video {
+    if stream_copy.video {
+        cmd.extend(vec!["-c:v".into(), "copy".into()]);
+
+        // Apple players only accept HEVC in mp4 and mov with the hvc1 tag
+        let is_hevc = media.first_video().and_then(|v| v.codec.as_deref()) == Some("hevc");
+
+        if is_hevc && matches!(output_format, "mp4" | "mov") {
+            cmd.extend(vec!["-tag:v".into(), "hvc1".into()]);
+        }
+    } else if !profile.disable_video {
         let encoder = if use_hw {
-            hw_accel_encoder
+            hw_accel_encoder.clone()
         } else {
-            profile.video
+            video_codec
         };
 
         cmd.push("-c:v".to_string());
         cmd.push(encoder);
 
-        if use_hw {
+        if let Some(bitrate) = &options.video_bitrate {
+            cmd.extend(vec!["-b:v".into(), bitrate.clone()]);
+        } else if use_hw {
+            // Hardware encoders have their own constant quality flags instead of -crf
+            if let Some(crf) = options.crf.or(profile.quality) {
+                override_hw_quality(&mut encoder_params, crf);
+            }
+
             cmd.extend(encoder_params.iter().map(|s| s.to_string()));
+        } else if let Some(crf) = options.crf.or(profile.quality) {
+            cmd.extend(vec!["-crf".into(), crf.to_string()]);
         } else if lossless {
             let crf = if output_format == "webm" { "30" } else { "18" };
             cmd.extend(vec!["-crf".into(), crf.into()]);
@@ -214,16 +374,59 @@ pub async fn ffmpeg_builder(input: &str, output_path: &str, output_format: &str)
                 "medium".into(),
             ]);
         }
+
+        if let Some(filter) = scale_filter {
+            cmd.extend(vec!["-vf".into(), filter]);
+        }
+
+        if let Some(frame_rate) = options.frame_rate {
+            cmd.extend(vec!["-r".into(), frame_rate.to_string()]);
+        }
     } else {
         cmd.push("-vn".to_string()); // if no video
     }
     cmd.extend(profile.arguments);
 
-    // Audio codec
-    cmd.push("-c:a".to_string());
-    cmd.push(profile.audio.to_string());
+    if media.streams_known() && !media.has_audio() {
+        cmd.push("-an".to_string()); // if no audio
+    } else if stream_copy.audio {
+        cmd.extend(vec!["-c:a".into(), "copy".into()]);
+    } else {
+        // Audio codec
+        cmd.push("-c:a".to_string());
+        cmd.push(
+            options
+                .audio_codec
+                .clone()
+                .unwrap_or_else(|| profile.audio.clone()),
+        );
+
+        if let Some(bitrate) = &options.audio_bitrate {
+            cmd.extend(vec!["-b:a".into(), bitrate.clone()]);
+        }
+
+        if let Some(sample_rate) = options.sample_rate {
+            cmd.extend(vec!["-ar".into(), sample_rate.to_string()]);
+        }
+    }
 
     cmd.push(output_path.to_string());
 
-    cmd
+    FfmpegArgs {
+        args: cmd,
+        hw_encoder: use_hw.then_some(hw_accel_encoder),
+        stream_copy,
+    }
+}
+
+// Replaces the constant quality value of NVENC (-cq), QSV (-global_quality) or VideoToolbox (-q:v)
+fn override_hw_quality(params: &mut [String], crf: u8) {
+    if let Some(pos) = params
+        .iter()
+        .position(|p| matches!(p.as_str(), "-cq" | "-global_quality" | "-q:v"))
+    {
+        if let Some(value) = params.get_mut(pos + 1) {
+            *value = crf.to_string();
+        }
+    }
 }
@@ -0,0 +1,521 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::State;
+use uuid::Uuid;
+
+use crate::{
+    helpers::{
+        ffmpeg_helper::ConversionOptions,
+        output_helper::{build_output_path, CollisionPolicy, OutputLocation, OutputRule},
+        preset_helper::find_preset,
+        queue_helper::{JobPriority, JobRequest, PipelineManager},
+        settings_helper::load_settings,
+    },
+    tools::error::Error,
+};
+
+// Same input lists as the frontend (supported.json), an extension that is both audio and video
+// counts as video just like in extensions.json
+const IMAGE_EXTENSIONS: [&str; 12] = [
+    "png", "jpeg", "jpg", "gif", "bmp", "tiff", "webp", "ico", "pnm", "tga", "dds", "farbfeld",
+];
+
+const AUDIO_EXTENSIONS: [&str; 13] = [
+    "aac", "aiff", "ape", "cda", "flac", "m4a", "m4b", "mp3", "oga", "opus", "wav", "wma", "bik",
+];
+
+const VIDEO_EXTENSIONS: [&str; 28] = [
+    "3g2", "3gp", "3gpp", "avi", "cavs", "dv", "dvr", "flv", "m2ts", "m4v", "mkv", "mod", "mov",
+    "mp4", "mpeg", "mpg", "mts", "mxf", "ogg", "ogv", "rm", "rmvb", "swf", "ts", "vob", "webm",
+    "wmv", "wtv",
+];
+
+#[derive(Debug, Serialize)]
+pub struct FileDetails {
+    pub id: String,
+    pub full_file_name: String,
+    pub file_extension: String,
+    pub file_name: String,
+    pub dir_path: String,
+}
+
+pub fn file_details(path: &str) -> Result<FileDetails, Error> {
+    let file_path = Path::new(path);
+
+    if !file_path.is_file() {
+        return Err(Error::NotAFile(path.to_string()));
+    }
+
+    let full_file_name = file_path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .ok_or(Error::InvalidPath)?
+        .to_string();
+    let file_extension = file_path
+        .extension()
+        .and_then(|s| s.to_str())
+        .ok_or(Error::InvalidPath)?
+        .to_string();
+    let file_name = file_path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .ok_or(Error::InvalidPath)?
+        .to_string();
+    let dir_path = file_path
+        .parent()
+        .and_then(|s| s.to_str())
+        .ok_or(Error::InvalidPath)?
+        .to_string();
+
+    Ok(FileDetails {
+        id: Uuid::new_v4().to_string(),
+        full_file_name,
+        file_extension,
+        file_name,
+        dir_path,
+    })
+}
+
+// "video", "audio" or "image", None for files that can't be converted
+pub fn file_category(extension: &str) -> Option<&'static str> {
+    let extension = extension.to_lowercase();
+    let extension = extension.as_str();
+
+    if IMAGE_EXTENSIONS.contains(&extension) {
+        Some("image")
+    } else if VIDEO_EXTENSIONS.contains(&extension) {
+        Some("video")
+    } else if AUDIO_EXTENSIONS.contains(&extension) {
+        Some("audio")
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FolderFilter {
+    pub recursive: bool,
+    // Empty means every supported category
+    pub categories: Vec<String>,
+    // Without the dot, compared case-insensitively
+    pub extensions: Vec<String>,
+    // Matched against the file name, "*" and "?" are supported, e.g. "IMG_*.jpg"
+    pub pattern: Option<String>,
+    // Bytes
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    // Unix timestamps in seconds, compared with the modification time
+    pub modified_after: Option<u64>,
+    pub modified_before: Option<u64>,
+    pub include_hidden: bool,
+    // Skips files that are already in the target format or whose output already exists
+    pub skip_converted: bool,
+}
+
+impl Default for FolderFilter {
+    fn default() -> Self {
+        Self {
+            recursive: false,
+            categories: vec![],
+            extensions: vec![],
+            pattern: None,
+            min_size: None,
+            max_size: None,
+            modified_after: None,
+            modified_before: None,
+            include_hidden: false,
+            skip_converted: true,
+        }
+    }
+}
+
+// Case-insensitive wildcard match, "*" matches any run of characters and "?" a single one
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // Position of the last "*" in the pattern and the name position it was tried at
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg_attr(not(target_os = "windows"), allow(unused_variables))]
+fn is_hidden(path: &Path, metadata: &fs::Metadata) -> bool {
+    let dot_file = path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .is_some_and(|name| name.starts_with('.'));
+
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::fs::MetadataExt;
+
+        // FILE_ATTRIBUTE_HIDDEN
+        if metadata.file_attributes() & 0x2 != 0 {
+            return true;
+        }
+    }
+
+    dot_file
+}
+
+impl FolderFilter {
+    // Images can only become images, and audio or video can't become an image
+    pub fn limit_to_target(&mut self, extension: &str) {
+        if self.categories.is_empty() {
+            self.categories = match file_category(extension) {
+                Some("image") => vec!["image".into()],
+                _ => vec!["video".into(), "audio".into()],
+            };
+        }
+    }
+
+    fn accepts(&self, path: &Path, metadata: &fs::Metadata) -> bool {
+        let Some(extension) = path.extension().and_then(|s| s.to_str()) else {
+            return false;
+        };
+
+        let Some(category) = file_category(extension) else {
+            return false;
+        };
+
+        if !self.categories.is_empty() && !self.categories.iter().any(|c| c == category) {
+            return false;
+        }
+
+        if !self.extensions.is_empty()
+            && !self
+                .extensions
+                .iter()
+                .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(extension))
+        {
+            return false;
+        }
+
+        if let Some(pattern) = &self.pattern {
+            let name = path
+                .file_name()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default();
+
+            if !matches_pattern(pattern, name) {
+                return false;
+            }
+        }
+
+        let size = metadata.len();
+
+        if self.min_size.is_some_and(|min| size < min)
+            || self.max_size.is_some_and(|max| size > max)
+        {
+            return false;
+        }
+
+        if self.modified_after.is_some() || self.modified_before.is_some() {
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs());
+
+            let Some(modified) = modified else {
+                return false;
+            };
+
+            if self.modified_after.is_some_and(|after| modified < after)
+                || self.modified_before.is_some_and(|before| modified > before)
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+// Files in a folder that pass the filter, sorted by path so batches keep the same order
+pub fn scan_folder(root: &Path, filter: &FolderFilter) -> Result<Vec<PathBuf>, Error> {
+    if !root.is_dir() {
+        return Err(Error::NotAFolder(root.to_string_lossy().to_string()));
+    }
+
+    let mut files = Vec::new();
+    let mut folders = vec![root.to_path_buf()];
+
+    while let Some(folder) = folders.pop() {
+        let entries = match fs::read_dir(&folder) {
+            Ok(entries) => entries,
+            // A sub folder without read permission shouldn't stop the whole scan
+            Err(e) if folder != root => {
+                log::error!("error reading folder {}: {}", folder.display(), e);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            // Symlinked folders are not followed, they can point back up the tree
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+
+            if !filter.include_hidden && is_hidden(&path, &metadata) {
+                continue;
+            }
+
+            if file_type.is_dir() {
+                if filter.recursive {
+                    folders.push(path);
+                }
+            } else if metadata.is_file() && filter.accepts(&path, &metadata) {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+fn already_converted(file: &Path, extension: &str, output_path: &Path) -> bool {
+    let same_format = file
+        .extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case(extension));
+
+    same_format || output_path.exists()
+}
+
+// Lists the files of a folder. With a target extension, already converted files can be skipped.
+#[tauri::command]
+pub async fn expand_folder(
+    path: String,
+    filter: Option<FolderFilter>,
+    extension: Option<String>,
+) -> Result<Vec<FileDetails>, Error> {
+    let filter = filter.unwrap_or_default();
+    let files = scan_folder(Path::new(&path), &filter)?;
+
+    let rule = load_settings().output;
+    let mut details = Vec::with_capacity(files.len());
+
+    for file in &files {
+        let file_str = file.to_string_lossy();
+
+        if let (Some(extension), true) = (&extension, filter.skip_converted) {
+            let output_path = build_output_path(
+                &file_str,
+                extension,
+                None,
+                &ConversionOptions::default(),
+                &rule,
+                details.len() + 1,
+            )
+            .await?;
+
+            if already_converted(file, extension, &output_path) {
+                continue;
+            }
+        }
+
+        details.push(file_details(&file_str)?);
+    }
+
+    Ok(details)
+}
+
+#[derive(Deserialize)]
+pub struct FolderJobRequest {
+    pub path: String,
+    pub extension: String,
+    #[serde(default)]
+    pub preset: Option<String>,
+    #[serde(default)]
+    pub options: ConversionOptions,
+    #[serde(default)]
+    pub filter: FolderFilter,
+    // The folder tree is recreated below this folder, None writes every output next to its input
+    #[serde(default)]
+    pub output_root: Option<String>,
+    #[serde(default)]
+    pub priority: JobPriority,
+    #[serde(default)]
+    pub collision: Option<CollisionPolicy>,
+}
+
+// Expands a folder and queues every file in it. Returns the queued files, their ids are the job ids.
+#[tauri::command]
+pub async fn enqueue_folder(
+    request: FolderJobRequest,
+    manager: State<'_, PipelineManager>,
+) -> Result<Vec<FileDetails>, Error> {
+    let extension = match &request.preset {
+        Some(name) => {
+            find_preset(name)
+                .ok_or_else(|| Error::PresetNotFound(name.clone()))?
+                .container
+        }
+        None => request.extension.clone(),
+    };
+
+    let mut filter = request.filter.clone();
+    filter.limit_to_target(&extension);
+
+    let files = scan_folder(Path::new(&request.path), &filter)?;
+
+    let template = load_settings().output.template;
+
+    let rule = OutputRule {
+        location: match &request.output_root {
+            Some(output_root) => OutputLocation::Mirror {
+                source_root: request.path.clone(),
+                target_root: output_root.clone(),
+            },
+            None => OutputLocation::Source,
+        },
+        template,
+    };
+
+    let mut details = Vec::new();
+    let mut jobs = Vec::new();
+
+    for file in &files {
+        let file_str = file.to_string_lossy().to_string();
+
+        if filter.skip_converted {
+            let output_path = build_output_path(
+                &file_str,
+                &extension,
+                request.preset.as_deref(),
+                &request.options,
+                &rule,
+                jobs.len() + 1,
+            )
+            .await?;
+
+            if already_converted(file, &extension, &output_path) {
+                continue;
+            }
+        }
+
+        let file_extension = file
+            .extension()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        let category = file_category(file_extension).unwrap_or("video");
+
+        let file = file_details(&file_str)?;
+
+        jobs.push(JobRequest {
+            id: file.id.clone(),
+            path: file_str,
+            extension: extension.clone(),
+            category: category.to_string(),
+            priority: request.priority,
+            preset: request.preset.clone(),
+            options: request.options.clone(),
+            collision: request.collision,
+            output: Some(rule.clone()),
+        });
+
+        details.push(file);
+    }
+
+    manager.add_jobs(jobs).await?;
+
+    Ok(details)
+}
+
+// A file forwarded by another instance, `path` is the absolute path to hand back to get_file_data
+#[derive(Debug, Serialize)]
+pub struct OpenedFile {
+    pub path: String,
+    #[serde(flatten)]
+    pub details: FileDetails,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RejectedFile {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct OpenedFiles {
+    pub files: Vec<OpenedFile>,
+    pub rejected: Vec<RejectedFile>,
+}
+
+impl OpenedFiles {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.rejected.is_empty()
+    }
+
+    fn push(&mut self, path: &Path) {
+        let path = path.to_string_lossy().to_string();
+
+        match file_details(&path) {
+            Ok(details) => self.files.push(OpenedFile { path, details }),
+            Err(e) => self.rejected.push(RejectedFile {
+                path,
+                error: e.to_string(),
+            }),
+        }
+    }
+}
+
+// Arguments of a second instance ("Open with Draconv", scripts...). Relative paths are resolved against
+// the working directory of that instance, folders are expanded like a dropped folder and flags are ignored.
+pub fn forwarded_files(argv: &[String], cwd: &str) -> OpenedFiles {
+    let mut opened = OpenedFiles::default();
+
+    for arg in argv.iter().skip(1).filter(|arg| !arg.starts_with('-')) {
+        let path = Path::new(cwd).join(arg);
+
+        if !path.is_dir() {
+            opened.push(&path);
+            continue;
+        }
+
+        match scan_folder(&path, &FolderFilter::default()) {
+            Ok(files) => files.iter().for_each(|file| opened.push(file)),
+            Err(e) => opened.rejected.push(RejectedFile {
+                path: path.to_string_lossy().to_string(),
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    opened
+}
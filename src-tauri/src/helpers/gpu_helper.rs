@@ -1,7 +1,14 @@
-use std::vec;
+use std::{
+    collections::HashSet,
+    sync::{Mutex, OnceLock},
+    vec,
+};
 use wgpu::{Backends, InstanceDescriptor, RequestAdapterOptions};
 
-use crate::helpers::{ffmpeg_helper::Encoder, settings_helper::load_settings};
+use crate::helpers::{
+    ffmpeg_helper::Encoder,
+    settings_helper::ConversionMode,
+};
 
 async fn detect_gpu() -> Result<u32, String> {
     let instance = wgpu::Instance::new(&InstanceDescriptor {
@@ -104,13 +111,29 @@ pub fn get_gpu_hw_encoder(base_codec: &str, vendor: GpuVendor) -> Option<&'stati
     }
 }
 
-pub async fn select_best_encoder() -> (Encoder, String, Vec<String>, String) {
-    let mut settings = load_settings();
+// Hardware encoders that failed to initialize in this session, jobs use the CPU instead of them
+static FAILED_HW_ENCODERS: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
 
-    if settings.conversion_mode == "lossless" || settings.conversion_mode != "hwaccel" {
+fn failed_hw_encoders() -> &'static Mutex<HashSet<String>> {
+    FAILED_HW_ENCODERS.get_or_init(|| Mutex::new(HashSet::new()))
+}
+
+pub fn mark_hw_encoder_failed(encoder: &str) {
+    failed_hw_encoders().lock().unwrap().insert(encoder.to_string());
+}
+
+pub fn is_hw_encoder_failed(encoder: &str) -> bool {
+    failed_hw_encoders().lock().unwrap().contains(encoder)
+}
+
+// `base_codec` is the CPU encoder the job would use, it is mapped to the matching hardware encoder
+pub async fn select_best_encoder(base_codec: &str, mode: ConversionMode) -> (Encoder, String, Vec<String>, String) {
+    let mut base_codec = base_codec.to_string();
+
+    if mode != ConversionMode::Hwaccel {
         return (
             Encoder::CPU,
-            settings.default_encoder.clone(),
+            base_codec,
             vec![],
             "none".into(),
         );
@@ -123,11 +146,14 @@ pub async fn select_best_encoder() -> (Encoder, String, Vec<String>, String) {
 
     let hw_accel_method = get_hw_accel_method(vendor_id);
 
-    if cfg!(target_os = "macos") && settings.default_encoder == "libsvtav1" {
-        settings.default_encoder = "libx265".into();
+    if cfg!(target_os = "macos") && base_codec == "libsvtav1" {
+        base_codec = "libx265".into();
     }
 
-    if let Some(encoder_name) = get_gpu_hw_encoder(settings.default_encoder.as_str(), vendor_id) {
+    let hw_encoder = get_gpu_hw_encoder(base_codec.as_str(), vendor_id)
+        .filter(|encoder| !is_hw_encoder_failed(encoder));
+
+    if let Some(encoder_name) = hw_encoder {
         match vendor_id {
             GpuVendor::AMD => (
                 Encoder::AMF,
@@ -197,7 +223,7 @@ pub async fn select_best_encoder() -> (Encoder, String, Vec<String>, String) {
             
             GpuVendor::UNKNOWN => (
                 Encoder::CPU,
-                settings.default_encoder,
+                base_codec,
                 vec![],
                 "none".into(),
             ),
@@ -205,7 +231,7 @@ pub async fn select_best_encoder() -> (Encoder, String, Vec<String>, String) {
     } else {
         (
             Encoder::CPU,
-            settings.default_encoder,
+            base_codec,
             vec![],
             "none".into(),
         )
@@ -0,0 +1,380 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::State;
+use uuid::Uuid;
+
+use crate::{
+    helpers::{
+        ffmpeg_helper::ConversionOptions,
+        journal_helper::now,
+        output_helper::{OutputLocation, OutputRule},
+        queue_helper::{JobPriority, JobRequest, PipelineJob, PipelineManager},
+    },
+    tools::{convert::ConversionReport, engine::data_path, error::Error},
+};
+
+// The oldest entries are dropped once the history is this long
+const MAX_ENTRIES: usize = 5000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryStatus {
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl HistoryStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            HistoryStatus::Completed => "completed",
+            HistoryStatus::Failed => "failed",
+            HistoryStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+// A finished, failed or cancelled job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    // Id of the job, a re-run gets a new one
+    pub id: String,
+    pub input_path: String,
+    pub output_path: String,
+    pub extension: String,
+    pub category: String,
+    pub preset: Option<String>,
+    #[serde(default)]
+    pub options: ConversionOptions,
+    #[serde(default)]
+    pub arguments: Vec<String>,
+    pub video_encoder: Option<String>,
+    pub audio_encoder: Option<String>,
+    // Bytes, the output size is only known for completed jobs
+    pub input_size: Option<u64>,
+    pub output_size: Option<u64>,
+    // Duration of the input in seconds
+    pub duration: Option<f64>,
+    // Seconds spent encoding, paused time doesn't count
+    pub encode_time: f64,
+    pub status: HistoryStatus,
+    pub error: Option<String>,
+    pub finished_at: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct HistoryQuery {
+    pub status: Option<HistoryStatus>,
+    // Part of the input or output path, case insensitive
+    pub search: Option<String>,
+    pub extension: Option<String>,
+    pub preset: Option<String>,
+    // Unix timestamps in seconds
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl HistoryQuery {
+    fn matches(&self, entry: &HistoryEntry) -> bool {
+        if self.status.is_some_and(|status| status != entry.status) {
+            return false;
+        }
+
+        if let Some(search) = &self.search {
+            let search = search.to_lowercase();
+
+            if !entry.input_path.to_lowercase().contains(&search)
+                && !entry.output_path.to_lowercase().contains(&search)
+            {
+                return false;
+            }
+        }
+
+        if let Some(extension) = &self.extension {
+            if !entry.extension.eq_ignore_ascii_case(extension) {
+                return false;
+            }
+        }
+
+        if self.preset.is_some() && self.preset != entry.preset {
+            return false;
+        }
+
+        !self.since.is_some_and(|since| entry.finished_at < since)
+            && !self.until.is_some_and(|until| entry.finished_at > until)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+// Serializes the read-modify-write cycles on the history file
+static HISTORY_LOCK: Mutex<()> = Mutex::new(());
+
+fn get_history_path() -> PathBuf {
+    data_path("history/history.json").expect("error resolving history file")
+}
+
+// A missing file is an empty history. A damaged one is copied to history.json.bak before anything can
+// replace it, and the error is returned so it isn't shown as an empty history.
+fn load_entries() -> Result<Vec<HistoryEntry>, Error> {
+    let path = get_history_path();
+
+    let Ok(data) = fs::read_to_string(&path) else {
+        return Ok(Vec::new());
+    };
+
+    serde_json::from_str(&data).map_err(|e| {
+        let backup = path.with_extension("json.bak");
+        fs::copy(&path, &backup).ok();
+
+        log::error!("error reading conversion history: {}", e);
+
+        Error::Io(format!(
+            "The conversion history is damaged, a copy was kept at {}: {}",
+            backup.display(),
+            e
+        ))
+    })
+}
+
+fn save_entries(entries: &[HistoryEntry]) -> Result<(), Error> {
+    let path = get_history_path();
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let json = serde_json::to_string(entries).map_err(|e| Error::Io(e.to_string()))?;
+
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, json)?;
+    fs::rename(&tmp_path, &path)?;
+
+    Ok(())
+}
+
+fn file_size(path: &str) -> Option<u64> {
+    fs::metadata(path).ok().map(|metadata| metadata.len())
+}
+
+// Adds a job to the history once it is done. Failing to write the history never fails the job.
+pub fn record_job(job: &PipelineJob, report: &ConversionReport, result: &Result<(), Error>) {
+    let (status, error) = match result {
+        Ok(_) => (HistoryStatus::Completed, None),
+        Err(Error::ConversionCancelled) => (HistoryStatus::Cancelled, None),
+        Err(e) => (HistoryStatus::Failed, Some(e.to_string())),
+    };
+
+    let entry = HistoryEntry {
+        id: job.id.to_string(),
+        input_path: job.path.clone(),
+        output_path: job.output_path.clone(),
+        extension: job.extension.clone(),
+        category: job.category.clone(),
+        preset: job.preset.clone(),
+        options: job.options.clone(),
+        arguments: report.arguments.clone(),
+        video_encoder: report.video_encoder.clone(),
+        audio_encoder: report.audio_encoder.clone(),
+        input_size: file_size(&job.path),
+        output_size: match status {
+            HistoryStatus::Completed => file_size(&job.output_path),
+            _ => None,
+        },
+        duration: report.duration,
+        encode_time: report.encode_time.as_secs_f64(),
+        status,
+        error,
+        finished_at: now(),
+    };
+
+    let _lock = HISTORY_LOCK.lock().unwrap();
+
+    // The damaged file was backed up by load_entries, the history starts over
+    let mut entries = load_entries().unwrap_or_default();
+
+    entries.push(entry);
+
+    if entries.len() > MAX_ENTRIES {
+        entries.drain(..entries.len() - MAX_ENTRIES);
+    }
+
+    if let Err(e) = save_entries(&entries) {
+        log::error!("error saving conversion history: {}", e);
+    }
+}
+
+// Matching entries, newest first
+fn find_entries(query: &HistoryQuery) -> Result<Vec<HistoryEntry>, Error> {
+    let entries = {
+        let _lock = HISTORY_LOCK.lock().unwrap();
+        load_entries()?
+    };
+
+    Ok(entries
+        .into_iter()
+        .rev()
+        .filter(|entry| query.matches(entry))
+        .skip(query.offset)
+        .take(query.limit.unwrap_or(usize::MAX))
+        .collect())
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn to_csv(entries: &[HistoryEntry]) -> String {
+    let mut csv = String::from(
+        "finished_at,status,input_path,output_path,extension,preset,video_encoder,audio_encoder,\
+         input_size,output_size,duration,encode_time,error,arguments\n",
+    );
+
+    let optional = |value: Option<String>| value.unwrap_or_default();
+
+    for entry in entries {
+        let finished_at = chrono::DateTime::from_timestamp(entry.finished_at as i64, 0)
+            .map(|date| date.with_timezone(&chrono::Local).to_rfc3339())
+            .unwrap_or_default();
+
+        let fields = [
+            finished_at,
+            entry.status.as_str().to_string(),
+            entry.input_path.clone(),
+            entry.output_path.clone(),
+            entry.extension.clone(),
+            optional(entry.preset.clone()),
+            optional(entry.video_encoder.clone()),
+            optional(entry.audio_encoder.clone()),
+            optional(entry.input_size.map(|size| size.to_string())),
+            optional(entry.output_size.map(|size| size.to_string())),
+            optional(entry.duration.map(|duration| format!("{:.3}", duration))),
+            format!("{:.3}", entry.encode_time),
+            optional(entry.error.clone()),
+            entry.arguments.join(" "),
+        ];
+
+        let line: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&line.join(","));
+        csv.push('\n');
+    }
+
+    csv
+}
+
+#[tauri::command]
+pub fn query_history(query: Option<HistoryQuery>) -> Result<Vec<HistoryEntry>, Error> {
+    find_entries(&query.unwrap_or_default())
+}
+
+// Writes the matching entries to a file and returns how many were exported
+#[tauri::command]
+pub fn export_history(
+    path: String,
+    format: ExportFormat,
+    query: Option<HistoryQuery>,
+) -> Result<usize, Error> {
+    let entries = find_entries(&query.unwrap_or_default())?;
+
+    let data = match format {
+        ExportFormat::Csv => to_csv(&entries),
+        ExportFormat::Json => {
+            serde_json::to_string_pretty(&entries).map_err(|e| Error::Io(e.to_string()))?
+        }
+    };
+
+    fs::write(path, data)?;
+
+    Ok(entries.len())
+}
+
+// Queues a past job again with the same settings and output folder. Returns the new job id.
+#[tauri::command]
+pub async fn rerun_history_entry(
+    id: String,
+    manager: State<'_, PipelineManager>,
+) -> Result<String, Error> {
+    let entry = {
+        let _lock = HISTORY_LOCK.lock().unwrap();
+        load_entries()?
+            .into_iter()
+            .rev()
+            .find(|entry| entry.id == id)
+    }
+    .ok_or(Error::HistoryEntryNotFound(id))?;
+
+    let output_path = Path::new(&entry.output_path);
+
+    // The old output name is reused as a template, unless it looks like one
+    let output = output_path
+        .parent()
+        .zip(output_path.file_stem().and_then(|s| s.to_str()))
+        .filter(|(_, stem)| !stem.contains(['{', '}']))
+        .map(|(folder, stem)| OutputRule {
+            location: OutputLocation::Folder {
+                path: folder.to_string_lossy().to_string(),
+            },
+            template: stem.to_string(),
+        });
+
+    let job_id = manager
+        .add_job(JobRequest {
+            id: Uuid::new_v4().to_string(),
+            path: entry.input_path,
+            extension: entry.extension,
+            category: entry.category,
+            priority: JobPriority::default(),
+            preset: entry.preset,
+            options: entry.options,
+            collision: None,
+            output,
+        })
+        .await?;
+
+    manager.try_dispatch();
+
+    Ok(job_id)
+}
+
+#[tauri::command]
+pub fn clear_history() -> Result<(), Error> {
+    let _lock = HISTORY_LOCK.lock().unwrap();
+
+    save_entries(&[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_fields_are_not_quoted() {
+        assert_eq!(csv_field("/videos/clip.mp4"), "/videos/clip.mp4");
+        assert_eq!(csv_field(""), "");
+    }
+
+    #[test]
+    fn special_characters_are_quoted() {
+        assert_eq!(csv_field("a, b"), "\"a, b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("line\nbreak"), "\"line\nbreak\"");
+        assert_eq!(csv_field("line\r\n"), "\"line\r\n\"");
+    }
+}
@@ -0,0 +1,238 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    helpers::{
+        ffmpeg_helper::ConversionOptions,
+        output_helper::{remove_temp_output, temp_output_path, CollisionPolicy},
+        queue_helper::JobPriority,
+    },
+    tools::engine::data_path,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+    // Job was running when the app was closed or crashed
+    Interrupted,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub id: String,
+    pub path: String,
+    pub extension: String,
+    pub category: String,
+    #[serde(default)]
+    pub priority: JobPriority,
+    #[serde(default)]
+    pub preset: Option<String>,
+    #[serde(default)]
+    pub options: ConversionOptions,
+    #[serde(default)]
+    pub target_path: Option<String>,
+    #[serde(default)]
+    pub output_path: Option<String>,
+    #[serde(default)]
+    pub collision: Option<CollisionPolicy>,
+    pub status: JobStatus,
+    pub error: Option<String>,
+    pub updated_at: u64,
+}
+
+impl JournalEntry {
+    pub fn new(id: String, path: String, extension: String, category: String) -> Self {
+        Self {
+            id,
+            path,
+            extension,
+            category,
+            priority: JobPriority::default(),
+            preset: None,
+            options: ConversionOptions::default(),
+            target_path: None,
+            output_path: None,
+            collision: None,
+            status: JobStatus::Queued,
+            error: None,
+            updated_at: now(),
+        }
+    }
+}
+
+// Disk-backed record of every job the pipeline has seen, so the queue survives restarts and crashes.
+// Kept in a std Mutex because every operation is a short in-memory update followed by a file write.
+pub struct QueueJournal {
+    path: PathBuf,
+    entries: Mutex<Vec<JournalEntry>>,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn get_journal_path() -> PathBuf {
+    data_path("queue/journal.json").expect("error resolving queue journal file")
+}
+
+impl QueueJournal {
+    pub fn load() -> Self {
+        let path = get_journal_path();
+
+        let entries: Vec<JournalEntry> = fs::read_to_string(&path)
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default();
+
+        Self {
+            path,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    // Called once on startup: jobs that were running when the process died are marked as interrupted,
+    // finished jobs are dropped and the still queued jobs are returned to be put back in the queue.
+    pub fn restore(&self) -> Vec<JournalEntry> {
+        let mut entries = self.entries.lock().unwrap();
+
+        entries.retain(|e| !matches!(e.status, JobStatus::Completed | JobStatus::Cancelled));
+
+        for entry in entries.iter_mut() {
+            if entry.status == JobStatus::Running {
+                entry.status = JobStatus::Interrupted;
+                entry.updated_at = now();
+
+                // The unfinished temp file of the job is of no use anymore
+                if let (Some(output_path), Ok(id)) =
+                    (&entry.output_path, Uuid::parse_str(&entry.id))
+                {
+                    remove_temp_output(&temp_output_path(output_path, id));
+                }
+            }
+        }
+
+        let pending = entries
+            .iter()
+            .filter(|e| e.status == JobStatus::Queued)
+            .cloned()
+            .collect();
+
+        self.persist(&entries);
+
+        pending
+    }
+
+    pub fn entries(&self) -> Vec<JournalEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    pub fn get(&self, id: &str) -> Option<JournalEntry> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|e| e.id == id)
+            .cloned()
+    }
+
+    pub fn record(&self, new_entries: Vec<JournalEntry>) {
+        let mut entries = self.entries.lock().unwrap();
+
+        for entry in new_entries {
+            match entries.iter_mut().find(|e| e.id == entry.id) {
+                Some(existing) => *existing = entry,
+                None => entries.push(entry),
+            }
+        }
+
+        self.persist(&entries);
+    }
+
+    pub fn set_status(&self, id: &str, status: JobStatus, error: Option<String>) {
+        let mut entries = self.entries.lock().unwrap();
+
+        if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
+            entry.status = status;
+            entry.error = error;
+            entry.updated_at = now();
+        }
+
+        self.persist(&entries);
+    }
+
+    pub fn set_status_many(&self, ids: &[String], status: JobStatus) {
+        let mut entries = self.entries.lock().unwrap();
+
+        for entry in entries.iter_mut().filter(|e| ids.contains(&e.id)) {
+            entry.status = status;
+            entry.updated_at = now();
+        }
+
+        self.persist(&entries);
+    }
+
+    pub fn set_priority(&self, id: &str, priority: JobPriority) {
+        let mut entries = self.entries.lock().unwrap();
+
+        if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
+            entry.priority = priority;
+            entry.updated_at = now();
+        }
+
+        self.persist(&entries);
+    }
+
+    // Keeps the queued entries in the given order so a restored queue has the same order
+    pub fn set_order(&self, ids: &[String]) {
+        let mut entries = self.entries.lock().unwrap();
+
+        entries.sort_by_key(|e| ids.iter().position(|id| *id == e.id).unwrap_or(usize::MAX));
+
+        self.persist(&entries);
+    }
+
+    // Removes everything that is not waiting or running anymore
+    pub fn clear_finished(&self) {
+        let mut entries = self.entries.lock().unwrap();
+
+        entries.retain(|e| matches!(e.status, JobStatus::Queued | JobStatus::Running));
+
+        self.persist(&entries);
+    }
+
+    fn persist(&self, entries: &[JournalEntry]) {
+        let json = match serde_json::to_string_pretty(entries) {
+            Ok(json) => json,
+            Err(e) => {
+                log::error!("error serializing queue journal: {}", e);
+                return;
+            }
+        };
+
+        if let Some(parent) = self.path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+
+        // Writing to a sibling file first so a crash mid-write can't leave a truncated journal
+        let tmp_path = self.path.with_extension("json.tmp");
+
+        if let Err(e) = fs::write(&tmp_path, json).and_then(|_| fs::rename(&tmp_path, &self.path)) {
+            log::error!("error writing queue journal: {}", e);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tauri_plugin_opener::open_path;
+use uuid::Uuid;
+
+use crate::tools::{engine::data_path, error::Error};
+
+// The oldest job logs are removed once there are more than this
+const MAX_JOB_LOGS: usize = 500;
+
+// How much FFmpeg writes to stderr, and so to the job log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FfmpegLogLevel {
+    Error,
+    #[default]
+    Warning,
+    Info,
+    Verbose,
+    Debug,
+}
+
+impl FfmpegLogLevel {
+    // Value of -loglevel, "level+" prefixes every line with its level so errors can be told apart
+    pub fn as_arg(&self) -> &'static str {
+        match self {
+            FfmpegLogLevel::Error => "level+error",
+            FfmpegLogLevel::Warning => "level+warning",
+            FfmpegLogLevel::Info => "level+info",
+            FfmpegLogLevel::Verbose => "level+verbose",
+            FfmpegLogLevel::Debug => "level+debug",
+        }
+    }
+}
+
+// Folder of the app log and the job logs
+pub fn log_dir() -> Result<PathBuf, Error> {
+    data_path("logs")
+}
+
+fn job_log_path(id: &Uuid) -> Result<PathBuf, Error> {
+    Ok(log_dir()?.join("jobs").join(format!("{}.log", id)))
+}
+
+// Keeps the newest job logs, they are only useful until the job is long forgotten
+fn prune_job_logs(folder: &Path) {
+    let Ok(entries) = fs::read_dir(folder) else {
+        return;
+    };
+
+    let mut logs: Vec<(PathBuf, std::time::SystemTime)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| Some((entry.path(), entry.metadata().ok()?.modified().ok()?)))
+        .collect();
+
+    if logs.len() <= MAX_JOB_LOGS {
+        return;
+    }
+
+    logs.sort_by_key(|(_, modified)| *modified);
+
+    for (path, _) in &logs[..logs.len() - MAX_JOB_LOGS] {
+        fs::remove_file(path).ok();
+    }
+}
+
+// Log file of a single job: the FFmpeg command lines, FFmpeg's stderr, retries and timings.
+// A log that can't be written is only reported in the app log, it never fails the job.
+pub struct JobLog {
+    file: Mutex<Option<File>>,
+}
+
+impl JobLog {
+    pub fn create(id: &Uuid) -> Self {
+        let file = job_log_path(id).and_then(|path| {
+            let folder = path.parent().ok_or(Error::InvalidPath)?;
+            fs::create_dir_all(folder)?;
+            prune_job_logs(folder);
+
+            File::create(path).map_err(Error::from)
+        });
+
+        let file = match file {
+            Ok(file) => Some(file),
+            Err(e) => {
+                log::error!("error creating the log of job {}: {}", id, e);
+                None
+            }
+        };
+
+        Self {
+            file: Mutex::new(file),
+        }
+    }
+
+    pub fn line(&self, text: &str) {
+        let mut file = self.file.lock().unwrap();
+
+        if let Some(f) = file.as_mut() {
+            let time = chrono::Local::now().format("%H:%M:%S%.3f");
+
+            if writeln!(f, "[{}] {}", time, text).is_err() {
+                // Disk full or similar, the rest of the log is dropped
+                *file = None;
+            }
+        }
+    }
+
+    // Arguments with spaces are quoted, so the line can be copied into a terminal
+    pub fn command(&self, program: &Path, args: &[String]) {
+        let mut line = format!("$ \"{}\"", program.display());
+
+        for arg in args {
+            if arg.is_empty() || arg.contains([' ', '"']) {
+                line.push_str(&format!(" \"{}\"", arg.replace('"', "\\\"")));
+            } else {
+                line.push(' ');
+                line.push_str(arg);
+            }
+        }
+
+        self.line(&line);
+    }
+}
+
+// The lines FFmpeg logged as errors, without their level tag. The whole output is used if no line is
+// tagged, e.g. when FFmpeg failed before it started logging.
+pub fn ffmpeg_errors(stderr: &VecDeque<String>) -> String {
+    const TAGS: [&str; 3] = ["[error] ", "[fatal] ", "[panic] "];
+
+    let errors: Vec<String> = stderr
+        .iter()
+        .filter(|line| TAGS.iter().any(|tag| line.contains(tag)))
+        .map(|line| {
+            TAGS.iter()
+                .fold(line.clone(), |line, tag| line.replace(tag, ""))
+        })
+        .collect();
+
+    if errors.is_empty() {
+        stderr.iter().cloned().collect::<Vec<_>>().join("\n")
+    } else {
+        errors.join("\n")
+    }
+}
+
+#[tauri::command]
+pub fn get_job_log(id: String) -> Result<String, Error> {
+    // Only job ids are accepted, so the path can't point outside the log folder
+    let job_id = Uuid::parse_str(&id).map_err(|_| Error::LogNotFound(id.clone()))?;
+    let path = job_log_path(&job_id)?;
+
+    fs::read_to_string(path).map_err(|_| Error::LogNotFound(id))
+}
+
+#[tauri::command]
+pub fn open_log_folder() -> Result<(), Error> {
+    let folder = log_dir()?;
+    fs::create_dir_all(&folder)?;
+
+    open_path(folder, None::<&str>).map_err(|e| Error::Io(e.to_string()))
+}
@@ -1,5 +1,18 @@
+pub mod discovery_helper;
+pub mod failure_helper;
 pub mod ffmpeg_helper;
+pub mod file_helper;
 pub mod gpu_helper;
+pub mod history_helper;
+pub mod journal_helper;
+pub mod log_helper;
 pub mod net_helper;
+pub mod output_helper;
+pub mod preset_helper;
+pub mod probe_helper;
+pub mod progress_helper;
 pub mod queue_helper;
+pub mod remux_helper;
 pub mod settings_helper;
+pub mod temp_helper;
+pub mod watch_helper;
@@ -0,0 +1,652 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    helpers::{
+        ffmpeg_helper::ConversionOptions, preset_helper::resolve_preset, probe_helper::probe,
+        settings_helper::load_settings,
+    },
+    tools::error::Error,
+};
+
+// Where the output of a job is written
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OutputLocation {
+    // Next to the input file
+    #[default]
+    Source,
+    // Every output in the same folder
+    Folder {
+        path: String,
+    },
+    // A folder next to the input file, e.g. "converted"
+    Subfolder {
+        name: String,
+    },
+    // The folder structure below `source_root` is recreated under `target_root`
+    Mirror {
+        source_root: String,
+        target_root: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct OutputRule {
+    pub location: OutputLocation,
+    // File name without the extension, see TEMPLATE_FIELDS
+    pub template: String,
+}
+
+impl Default for OutputRule {
+    fn default() -> Self {
+        Self {
+            location: OutputLocation::default(),
+            template: "{stem}".into(),
+        }
+    }
+}
+
+const TEMPLATE_FIELDS: [&str; 8] = [
+    "stem", "ext", "preset", "codec", "width", "height", "date", "index",
+];
+
+// Characters that are not allowed in file names on at least one of the supported platforms
+const ILLEGAL_CHARS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+enum TemplatePart<'a> {
+    Text(&'a str),
+    Field(&'a str),
+}
+
+fn parse_template(template: &str) -> Result<Vec<TemplatePart<'_>>, Error> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find(['{', '}']) {
+        if rest[start..].starts_with('}') {
+            return Err(Error::InvalidOutputRule(format!(
+                "Unexpected '}}' in template: {}",
+                template
+            )));
+        }
+
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| {
+                Error::InvalidOutputRule(format!("Unclosed '{{' in template: {}", template))
+            })?;
+
+        let field = &rest[start + 1..end];
+
+        if !TEMPLATE_FIELDS.contains(&field) {
+            return Err(Error::InvalidOutputRule(format!(
+                "Unknown template field: {{{}}}",
+                field
+            )));
+        }
+
+        if start > 0 {
+            parts.push(TemplatePart::Text(&rest[..start]));
+        }
+
+        parts.push(TemplatePart::Field(field));
+        rest = &rest[end + 1..];
+    }
+
+    if !rest.is_empty() {
+        parts.push(TemplatePart::Text(rest));
+    }
+
+    Ok(parts)
+}
+
+fn is_illegal(c: char) -> bool {
+    ILLEGAL_CHARS.contains(&c) || c.is_control()
+}
+
+fn has_illegal_chars(value: &str) -> bool {
+    value.chars().any(is_illegal)
+}
+
+// Values put into a template can contain anything, e.g. a preset named "H.264 / AAC"
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if is_illegal(c) { '_' } else { c })
+        .collect()
+}
+
+fn validate_file_name(name: &str) -> Result<(), Error> {
+    if name.trim().is_empty() {
+        return Err(Error::InvalidOutputRule("The file name is empty".into()));
+    }
+
+    if has_illegal_chars(name) {
+        return Err(Error::InvalidOutputRule(format!(
+            "The file name contains illegal characters: {}",
+            name
+        )));
+    }
+
+    // Windows silently drops these, so "clip." and "clip" would be the same file
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Err(Error::InvalidOutputRule(format!(
+            "The file name can't end with a dot or a space: {}",
+            name
+        )));
+    }
+
+    let base = name.split('.').next().unwrap_or_default().to_uppercase();
+
+    if RESERVED_NAMES.contains(&base.as_str()) {
+        return Err(Error::InvalidOutputRule(format!(
+            "The file name is reserved: {}",
+            name
+        )));
+    }
+
+    Ok(())
+}
+
+impl OutputRule {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.template.trim().is_empty() {
+            return Err(Error::InvalidOutputRule("The template is empty".into()));
+        }
+
+        for part in parse_template(&self.template)? {
+            if let TemplatePart::Text(text) = part {
+                if has_illegal_chars(text) {
+                    return Err(Error::InvalidOutputRule(format!(
+                        "The template contains illegal characters: {}",
+                        self.template
+                    )));
+                }
+            }
+        }
+
+        match &self.location {
+            OutputLocation::Source => (),
+            OutputLocation::Folder { path } => {
+                if !Path::new(path).is_absolute() {
+                    return Err(Error::InvalidOutputRule(format!(
+                        "The output folder must be an absolute path: {}",
+                        path
+                    )));
+                }
+            }
+            OutputLocation::Subfolder { name } => {
+                let subfolder = Path::new(name);
+
+                let escapes = subfolder
+                    .components()
+                    .any(|c| !matches!(c, std::path::Component::Normal(_)));
+
+                if name.trim().is_empty() || escapes {
+                    return Err(Error::InvalidOutputRule(format!(
+                        "The subfolder must be a relative path inside the input folder: {}",
+                        name
+                    )));
+                }
+            }
+            OutputLocation::Mirror {
+                source_root,
+                target_root,
+            } => {
+                if !Path::new(source_root).is_absolute() || !Path::new(target_root).is_absolute() {
+                    return Err(Error::InvalidOutputRule(
+                        "Both folders of a mirrored output must be absolute paths".into(),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn output_dir(&self, input: &Path) -> Result<PathBuf, Error> {
+        let parent = input.parent().ok_or(Error::InvalidPath)?;
+
+        match &self.location {
+            OutputLocation::Source => Ok(parent.to_path_buf()),
+            OutputLocation::Folder { path } => Ok(PathBuf::from(path)),
+            OutputLocation::Subfolder { name } => Ok(parent.join(name)),
+            OutputLocation::Mirror {
+                source_root,
+                target_root,
+            } => {
+                let relative = parent.strip_prefix(source_root).map_err(|_| {
+                    Error::InvalidOutputRule(format!(
+                        "{} is not inside the mirrored folder {}",
+                        input.display(),
+                        source_root
+                    ))
+                })?;
+
+                Ok(Path::new(target_root).join(relative))
+            }
+        }
+    }
+}
+
+fn uses_field(parts: &[TemplatePart], names: &[&str]) -> bool {
+    parts
+        .iter()
+        .any(|part| matches!(part, TemplatePart::Field(field) if names.contains(field)))
+}
+
+// Output size of a job, a side that is not set in the options keeps the aspect ratio of the input
+async fn output_dimensions(input: &str, options: &ConversionOptions) -> (Option<u32>, Option<u32>) {
+    if let (Some(width), Some(height)) = (options.width, options.height) {
+        return (Some(width), Some(height));
+    }
+
+    let media = probe(input).await.ok();
+    let video = media.as_ref().and_then(|m| m.first_video());
+
+    let (Some(input_width), Some(input_height)) =
+        (video.and_then(|v| v.width), video.and_then(|v| v.height))
+    else {
+        return (options.width, options.height);
+    };
+
+    // Same rounding as the "-2" of the scale filter
+    let scale = |side: u32, from: u32, to: u32| {
+        ((side as f64 * to as f64 / from as f64 / 2.0).round() as u32) * 2
+    };
+
+    match (options.width, options.height) {
+        (Some(width), None) => (Some(width), Some(scale(width, input_width, input_height))),
+        (None, Some(height)) => (Some(scale(height, input_height, input_width)), Some(height)),
+        _ => (Some(input_width), Some(input_height)),
+    }
+}
+
+// Output path a job gets from an output rule, before the collision policy is applied.
+// The input is only probed if the template needs the size of the output.
+pub async fn build_output_path(
+    input: &str,
+    extension: &str,
+    preset: Option<&str>,
+    options: &ConversionOptions,
+    rule: &OutputRule,
+    index: usize,
+) -> Result<PathBuf, Error> {
+    rule.validate()?;
+
+    let file = Path::new(input);
+    let stem = file
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .ok_or(Error::InvalidPath)?;
+
+    let parts = parse_template(&rule.template)?;
+
+    let (width, height) = if uses_field(&parts, &["width", "height"]) {
+        output_dimensions(input, options).await
+    } else {
+        (None, None)
+    };
+
+    let codec = if uses_field(&parts, &["codec"]) {
+        let preset = resolve_preset(preset, extension);
+
+        if preset.disable_video {
+            options.audio_codec.clone().unwrap_or(preset.audio_codec)
+        } else {
+            options
+                .video_codec
+                .clone()
+                .or(preset.video_codec)
+                .unwrap_or_else(|| load_settings().default_encoder.as_str().to_string())
+        }
+    } else {
+        String::new()
+    };
+
+    let mut name = String::new();
+
+    for part in parts {
+        let value = match part {
+            TemplatePart::Text(text) => text.to_string(),
+            TemplatePart::Field("stem") => stem.to_string(),
+            TemplatePart::Field("ext") => extension.to_string(),
+            TemplatePart::Field("preset") => sanitize(preset.unwrap_or(extension)),
+            TemplatePart::Field("codec") => sanitize(&codec),
+            TemplatePart::Field("width") => width.map(|w| w.to_string()).unwrap_or_default(),
+            TemplatePart::Field("height") => height.map(|h| h.to_string()).unwrap_or_default(),
+            TemplatePart::Field("date") => chrono::Local::now().format("%Y-%m-%d").to_string(),
+            TemplatePart::Field("index") => index.to_string(),
+            TemplatePart::Field(_) => String::new(),
+        };
+
+        name.push_str(&value);
+    }
+
+    validate_file_name(&name)?;
+
+    Ok(rule
+        .output_dir(file)?
+        .join(format!("{}.{}", name, extension)))
+}
+
+#[tauri::command]
+pub async fn preview_output_path(
+    path: String,
+    extension: String,
+    preset: Option<String>,
+    options: Option<ConversionOptions>,
+    output: Option<OutputRule>,
+    index: Option<usize>,
+) -> Result<String, Error> {
+    let rule = output.unwrap_or_else(|| load_settings().output);
+
+    let output_path = build_output_path(
+        &path,
+        &extension,
+        preset.as_deref(),
+        &options.unwrap_or_default(),
+        &rule,
+        index.unwrap_or(1),
+    )
+    .await?;
+
+    Ok(output_path.to_string_lossy().to_string())
+}
+
+// What happens when the output path of a job is already taken by a file or by another job
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CollisionPolicy {
+    // "clip.mp4" becomes "clip (1).mp4", "clip (2).mp4"...
+    #[default]
+    AutoSuffix,
+    // The job is not queued
+    Skip,
+    // Existing files are replaced, but never the input file or the output of another job
+    Overwrite,
+    // The job is not queued and the frontend is asked to decide
+    Ask,
+}
+
+// Outcome of the collision check for a single job
+pub enum OutputResolution {
+    Path(PathBuf),
+    Skip(PathBuf),
+}
+
+// Output next to the input with the new extension
+pub fn default_output_path(path: &str, extension: &str) -> Result<PathBuf, Error> {
+    let file = Path::new(path);
+    let stem = file
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .ok_or(Error::InvalidPath)?;
+    let parent = file.parent().ok_or(Error::InvalidPath)?;
+
+    Ok(parent.join(format!("{}.{}", stem, extension)))
+}
+
+// Paths are compared after resolving the directory, so "./a.mp4" and "/dir/a.mp4" are the same file.
+// The output file usually doesn't exist yet, so only its parent can be canonicalized.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let normalized = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => parent
+            .canonicalize()
+            .map(|p| p.join(name))
+            .unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.to_path_buf(),
+    };
+
+    // Windows and macOS file systems are case-insensitive by default
+    if cfg!(any(target_os = "windows", target_os = "macos")) {
+        PathBuf::from(normalized.to_string_lossy().to_lowercase())
+    } else {
+        normalized
+    }
+}
+
+fn with_suffix(path: &Path, index: u32) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+
+    let name = match path.extension().and_then(|s| s.to_str()) {
+        Some(extension) => format!("{} ({}).{}", stem, index, extension),
+        None => format!("{} ({})", stem, index),
+    };
+
+    path.with_file_name(name)
+}
+
+// Decides the final output path of a job. `reserved` holds the normalized outputs of the jobs
+// that are already queued or running, the input file is never used as an output.
+pub fn resolve_output_path(
+    input: &str,
+    candidate: PathBuf,
+    policy: CollisionPolicy,
+    reserved: &[PathBuf],
+) -> Result<OutputResolution, Error> {
+    let input = normalize_path(Path::new(input));
+
+    let is_input = |path: &Path| normalize_path(path) == input;
+    let is_reserved = |path: &Path| reserved.contains(&normalize_path(path));
+
+    let collides = is_input(&candidate) || is_reserved(&candidate);
+
+    if !collides && !candidate.exists() {
+        return Ok(OutputResolution::Path(candidate));
+    }
+
+    match policy {
+        CollisionPolicy::Skip => Ok(OutputResolution::Skip(candidate)),
+        CollisionPolicy::Ask => Err(Error::OutputExists(candidate.to_string_lossy().to_string())),
+        CollisionPolicy::Overwrite if !collides => Ok(OutputResolution::Path(candidate)),
+        CollisionPolicy::AutoSuffix | CollisionPolicy::Overwrite => {
+            let mut index = 1;
+
+            loop {
+                let path = with_suffix(&candidate, index);
+
+                if !path.exists() && !is_input(&path) && !is_reserved(&path) {
+                    return Ok(OutputResolution::Path(path));
+                }
+
+                index += 1;
+            }
+        }
+    }
+}
+
+// Jobs write into a hidden file next to the output and it is renamed when the conversion is done,
+// so an interrupted conversion never leaves a file that looks finished. The extension is kept
+// because FFmpeg and the image crate pick the output format from it.
+pub fn temp_output_path(output_path: &str, id: Uuid) -> PathBuf {
+    let output = Path::new(output_path);
+
+    let name = match output.extension().and_then(|s| s.to_str()) {
+        Some(extension) => format!(".draconv-{}.{}", id.simple(), extension),
+        None => format!(".draconv-{}", id.simple()),
+    };
+
+    output.with_file_name(name)
+}
+
+// Moves a finished temp file to the output path. Both are in the same directory, so the rename is
+// atomic and replaces the file for the overwrite policy.
+pub fn commit_output(temp_path: &Path, output_path: &str) -> Result<(), Error> {
+    let size = fs::metadata(temp_path).map(|m| m.len()).unwrap_or(0);
+
+    if size == 0 {
+        remove_temp_output(temp_path);
+        return Err(Error::OutputVerification("The output file is empty".into()));
+    }
+
+    if let Err(e) = fs::rename(temp_path, output_path) {
+        remove_temp_output(temp_path);
+        return Err(e.into());
+    }
+
+    Ok(())
+}
+
+pub fn remove_temp_output(temp_path: &Path) {
+    if temp_path.exists() {
+        fs::remove_file(temp_path).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Fields are written as "[name]" so they can't be mistaken for text
+    fn parts(template: &str) -> Vec<String> {
+        parse_template(template)
+            .unwrap()
+            .iter()
+            .map(|part| match part {
+                TemplatePart::Text(text) => text.to_string(),
+                TemplatePart::Field(field) => format!("[{}]", field),
+            })
+            .collect()
+    }
+
+    // Empty folder for a single test in the temp directory
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("draconv-output-{}", std::process::id()))
+            .join(name);
+
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn resolved(resolution: OutputResolution) -> PathBuf {
+        match resolution {
+            OutputResolution::Path(path) => path,
+            OutputResolution::Skip(path) => panic!("{} was skipped", path.display()),
+        }
+    }
+
+    #[test]
+    fn parses_fields_and_text() {
+        assert_eq!(
+            parts("{stem}_{width}x{height}"),
+            ["[stem]", "_", "[width]", "x", "[height]"]
+        );
+        assert_eq!(parts("converted {date}"), ["converted ", "[date]"]);
+        assert_eq!(parts("plain"), ["plain"]);
+    }
+
+    #[test]
+    fn rejects_broken_templates() {
+        for template in ["{stem", "stem}", "{size}", "{}"] {
+            assert!(
+                matches!(parse_template(template), Err(Error::InvalidOutputRule(_))),
+                "{} was accepted",
+                template
+            );
+        }
+    }
+
+    #[test]
+    fn free_path_is_kept() {
+        let dir = temp_dir("free-path");
+        let candidate = dir.join("clip.mkv");
+
+        let path = resolved(
+            resolve_output_path(
+                &dir.join("clip.mp4").to_string_lossy(),
+                candidate.clone(),
+                CollisionPolicy::AutoSuffix,
+                &[],
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(path, candidate);
+    }
+
+    #[test]
+    fn taken_paths_get_a_suffix() {
+        let dir = temp_dir("suffix");
+        let input = dir.join("clip.mp4");
+        let candidate = dir.join("clip.mkv");
+
+        fs::write(&candidate, "existing").unwrap();
+        let reserved = [normalize_path(&dir.join("clip (1).mkv"))];
+
+        let path = resolved(
+            resolve_output_path(
+                &input.to_string_lossy(),
+                candidate,
+                CollisionPolicy::AutoSuffix,
+                &reserved,
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(path, dir.join("clip (2).mkv"));
+    }
+
+    #[test]
+    fn overwrite_never_replaces_the_input() {
+        let dir = temp_dir("overwrite");
+        let input = dir.join("clip.mp4");
+        let existing = dir.join("other.mp4");
+
+        fs::write(&input, "input").unwrap();
+        fs::write(&existing, "existing").unwrap();
+
+        let resolve = |candidate: &Path| {
+            resolved(
+                resolve_output_path(
+                    &input.to_string_lossy(),
+                    candidate.to_path_buf(),
+                    CollisionPolicy::Overwrite,
+                    &[],
+                )
+                .unwrap(),
+            )
+        };
+
+        assert_eq!(resolve(&existing), existing);
+        assert_eq!(resolve(&input), dir.join("clip (1).mp4"));
+    }
+
+    #[test]
+    fn skip_and_ask_leave_the_file_alone() {
+        let dir = temp_dir("skip-ask");
+        let input = dir.join("clip.mp4").to_string_lossy().to_string();
+        let candidate = dir.join("clip.mkv");
+
+        fs::write(&candidate, "existing").unwrap();
+
+        assert!(matches!(
+            resolve_output_path(&input, candidate.clone(), CollisionPolicy::Skip, &[]),
+            Ok(OutputResolution::Skip(_))
+        ));
+        assert!(matches!(
+            resolve_output_path(&input, candidate, CollisionPolicy::Ask, &[]),
+            Err(Error::OutputExists(_))
+        ));
+    }
+}
@@ -0,0 +1,280 @@
+use std::{fs, path::PathBuf, sync::Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::tools::{engine::data_path, error::Error};
+
+// Named conversion preset. Built-in presets are named after their container and reproduce
+// the default behavior, a user preset with the same name replaces the built-in one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Preset {
+    pub name: String,
+    // Output file extension
+    pub container: String,
+    // None uses the default encoder from the settings
+    #[serde(default)]
+    pub video_codec: Option<String>,
+    pub audio_codec: String,
+    #[serde(default)]
+    pub disable_video: bool,
+    // CRF value, None uses the conversion mode from the settings
+    #[serde(default)]
+    pub quality: Option<u8>,
+    #[serde(default)]
+    pub extra_args: Vec<String>,
+    #[serde(default)]
+    pub hwaccel_supported: bool,
+    #[serde(default, skip_deserializing)]
+    pub builtin: bool,
+}
+
+impl Preset {
+    fn builtin(
+        container: &str,
+        video_codec: Option<&str>,
+        audio_codec: &str,
+        disable_video: bool,
+        hwaccel_supported: bool,
+        extra_args: Vec<String>,
+    ) -> Self {
+        Self {
+            name: container.to_string(),
+            container: container.to_string(),
+            video_codec: video_codec.map(|s| s.to_string()),
+            audio_codec: audio_codec.to_string(),
+            disable_video,
+            quality: None,
+            extra_args,
+            hwaccel_supported,
+            builtin: true,
+        }
+    }
+
+    // Used for containers without a built-in preset
+    pub fn fallback(container: &str) -> Self {
+        Self {
+            builtin: false,
+            ..Self::builtin(container, None, "aac", false, true, vec![])
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.name.trim().is_empty() {
+            return Err(Error::InvalidPreset("Preset name can't be empty".into()));
+        }
+
+        if self.container.is_empty() || !self.container.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(Error::InvalidPreset(format!(
+                "Invalid container: {}",
+                self.container
+            )));
+        }
+
+        if self.audio_codec.trim().is_empty() {
+            return Err(Error::InvalidPreset("Audio codec can't be empty".into()));
+        }
+
+        Ok(())
+    }
+}
+
+pub fn builtin_presets() -> Vec<Preset> {
+    vec![
+        Preset::builtin("mp4", None, "aac", false, true, vec![]),
+        Preset::builtin("webm", Some("libvpx-vp9"), "libopus", false, false, vec![]),
+        Preset::builtin("mov", None, "aac", false, true, vec![]),
+        Preset::builtin(
+            "avi",
+            Some("mpeg4"),
+            "mp3",
+            false,
+            false,
+            vec!["-quality".into(), "quality".into()],
+        ),
+        Preset::builtin("mkv", None, "libopus", false, true, vec![]),
+        Preset::builtin("flv", Some("flv"), "mp3", false, false, vec![]),
+        Preset::builtin("wmv", Some("msmpeg4"), "wmav2", false, false, vec![]),
+        Preset::builtin("mp3", None, "libmp3lame", true, false, vec![]),
+        Preset::builtin("aac", None, "aac", true, false, vec![]),
+        Preset::builtin("flac", None, "flac", true, false, vec![]),
+        Preset::builtin("wav", None, "pcm_s16le", true, false, vec![]),
+        Preset::builtin("ogg", None, "libopus", true, false, vec![]),
+    ]
+}
+
+// Serializes the read-modify-write cycles on the presets file
+static PRESETS_LOCK: Mutex<()> = Mutex::new(());
+
+fn get_presets_path() -> PathBuf {
+    data_path("presets/presets.json").expect("error resolving presets file")
+}
+
+fn load_user_presets() -> Vec<Preset> {
+    let path = get_presets_path();
+
+    let Ok(data) = fs::read_to_string(&path) else {
+        return Vec::new();
+    };
+
+    match serde_json::from_str(&data) {
+        Ok(presets) => presets,
+        Err(e) => {
+            // The next save replaces the broken file, the backup keeps the presets that were in it
+            log::error!("error reading user presets, a backup is kept: {}", e);
+            fs::copy(&path, path.with_extension("json.bak")).ok();
+            Vec::new()
+        }
+    }
+}
+
+fn save_user_presets(presets: &[Preset]) -> Result<(), Error> {
+    let path = get_presets_path();
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let json = serde_json::to_string_pretty(presets).map_err(|e| Error::Io(e.to_string()))?;
+
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, json)?;
+    fs::rename(&tmp_path, &path)?;
+
+    Ok(())
+}
+
+// Looks a preset up by name, user presets first
+pub fn find_preset(name: &str) -> Option<Preset> {
+    load_user_presets()
+        .into_iter()
+        .find(|p| p.name == name)
+        .or_else(|| builtin_presets().into_iter().find(|p| p.name == name))
+}
+
+// Preset used by a job: the named one if given, otherwise the one named after the output extension
+pub fn resolve_preset(name: Option<&str>, container: &str) -> Preset {
+    name.and_then(find_preset)
+        .or_else(|| find_preset(container))
+        .unwrap_or_else(|| Preset::fallback(container))
+}
+
+#[tauri::command]
+pub fn list_presets() -> Vec<Preset> {
+    let user = load_user_presets();
+
+    let mut presets: Vec<Preset> = builtin_presets()
+        .into_iter()
+        .filter(|b| !user.iter().any(|u| u.name == b.name))
+        .collect();
+
+    presets.extend(user);
+
+    presets
+}
+
+#[tauri::command]
+pub fn create_preset(preset: Preset) -> Result<(), Error> {
+    preset.validate()?;
+
+    let _lock = PRESETS_LOCK.lock().unwrap();
+    let mut presets = load_user_presets();
+
+    if presets.iter().any(|p| p.name == preset.name)
+        || builtin_presets().iter().any(|p| p.name == preset.name)
+    {
+        return Err(Error::PresetExists(preset.name));
+    }
+
+    presets.push(preset);
+
+    save_user_presets(&presets)
+}
+
+// Updating a built-in preset stores a user copy that replaces it
+#[tauri::command]
+pub fn update_preset(name: String, preset: Preset) -> Result<(), Error> {
+    preset.validate()?;
+
+    let _lock = PRESETS_LOCK.lock().unwrap();
+    let mut presets = load_user_presets();
+
+    let is_builtin = builtin_presets().iter().any(|p| p.name == name);
+    let position = presets.iter().position(|p| p.name == name);
+
+    if position.is_none() && !is_builtin {
+        return Err(Error::PresetNotFound(name));
+    }
+
+    if preset.name != name && presets.iter().any(|p| p.name == preset.name) {
+        return Err(Error::PresetExists(preset.name));
+    }
+
+    match position {
+        Some(pos) => presets[pos] = preset,
+        None => presets.push(preset),
+    }
+
+    save_user_presets(&presets)
+}
+
+// Deleting a replaced built-in preset brings the original one back
+#[tauri::command]
+pub fn delete_preset(name: String) -> Result<(), Error> {
+    let _lock = PRESETS_LOCK.lock().unwrap();
+    let mut presets = load_user_presets();
+
+    let position = presets.iter().position(|p| p.name == name).ok_or_else(|| {
+        if builtin_presets().iter().any(|p| p.name == name) {
+            Error::InvalidPreset(format!("Built-in preset can't be deleted: {}", name))
+        } else {
+            Error::PresetNotFound(name.clone())
+        }
+    })?;
+
+    presets.remove(position);
+
+    save_user_presets(&presets)
+}
+
+// Imports presets from a JSON file, presets with the same name are replaced
+#[tauri::command]
+pub fn import_presets(path: String) -> Result<usize, Error> {
+    let data = fs::read_to_string(&path)?;
+
+    let imported: Vec<Preset> = serde_json::from_str(&data)
+        .or_else(|_| serde_json::from_str::<Preset>(&data).map(|p| vec![p]))
+        .map_err(|e| Error::InvalidPreset(e.to_string()))?;
+
+    for preset in &imported {
+        preset.validate()?;
+    }
+
+    let _lock = PRESETS_LOCK.lock().unwrap();
+    let mut presets = load_user_presets();
+    let count = imported.len();
+
+    for preset in imported {
+        match presets.iter().position(|p| p.name == preset.name) {
+            Some(pos) => presets[pos] = preset,
+            None => presets.push(preset),
+        }
+    }
+
+    save_user_presets(&presets)?;
+
+    Ok(count)
+}
+
+// Exports the user presets, or only the given ones, to a JSON file
+#[tauri::command]
+pub fn export_presets(path: String, names: Option<Vec<String>>) -> Result<(), Error> {
+    let presets: Vec<Preset> = load_user_presets()
+        .into_iter()
+        .filter(|p| names.as_ref().map_or(true, |names| names.contains(&p.name)))
+        .collect();
+
+    let json = serde_json::to_string_pretty(&presets).map_err(|e| Error::Io(e.to_string()))?;
+    fs::write(path, json)?;
+
+    Ok(())
+}
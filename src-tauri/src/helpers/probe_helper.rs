@@ -0,0 +1,316 @@
+use std::{collections::HashMap, process::Stdio};
+
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use crate::{
+    helpers::ffmpeg_helper::{get_ffmpeg_path, get_ffprobe_path, get_video_duration_millis},
+    tools::error::Error,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamKind {
+    Video,
+    Audio,
+    Subtitle,
+    Data,
+    Attachment,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamInfo {
+    pub index: u32,
+    pub kind: StreamKind,
+    pub codec: Option<String>,
+    pub profile: Option<String>,
+    pub bit_rate: Option<u64>,
+    pub language: Option<String>,
+    pub title: Option<String>,
+    // Video
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub frame_rate: Option<f64>,
+    pub pixel_format: Option<String>,
+    pub rotation: Option<i32>,
+    pub frames: Option<u64>,
+    // Audio
+    pub channels: Option<u32>,
+    pub channel_layout: Option<String>,
+    pub sample_rate: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChapterInfo {
+    pub start: f64,
+    pub end: f64,
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AttachmentInfo {
+    pub file_name: Option<String>,
+    pub mime_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MediaInfo {
+    pub container: Option<String>,
+    // Seconds, None if the input has no known duration (e.g. live streams or broken files)
+    pub duration: Option<f64>,
+    pub bit_rate: Option<u64>,
+    pub size: Option<u64>,
+    pub streams: Vec<StreamInfo>,
+    pub chapters: Vec<ChapterInfo>,
+    pub attachments: Vec<AttachmentInfo>,
+}
+
+impl MediaInfo {
+    pub fn video_streams(&self) -> impl Iterator<Item = &StreamInfo> {
+        self.streams.iter().filter(|s| s.kind == StreamKind::Video)
+    }
+
+    pub fn audio_streams(&self) -> impl Iterator<Item = &StreamInfo> {
+        self.streams.iter().filter(|s| s.kind == StreamKind::Audio)
+    }
+
+    pub fn first_video(&self) -> Option<&StreamInfo> {
+        self.video_streams().next()
+    }
+
+    pub fn has_video(&self) -> bool {
+        self.first_video().is_some()
+    }
+
+    pub fn has_audio(&self) -> bool {
+        self.audio_streams().next().is_some()
+    }
+
+    // Probing without ffprobe can't see the streams, so they are assumed to exist
+    pub fn streams_known(&self) -> bool {
+        !self.streams.is_empty()
+    }
+}
+
+// ffprobe prints most numbers as strings, so everything is read as text and parsed afterwards
+#[derive(Deserialize)]
+struct RawProbe {
+    #[serde(default)]
+    format: Option<RawFormat>,
+    #[serde(default)]
+    streams: Vec<RawStream>,
+    #[serde(default)]
+    chapters: Vec<RawChapter>,
+}
+
+#[derive(Deserialize)]
+struct RawFormat {
+    format_name: Option<String>,
+    duration: Option<String>,
+    bit_rate: Option<String>,
+    size: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawStream {
+    index: u32,
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    profile: Option<String>,
+    bit_rate: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    avg_frame_rate: Option<String>,
+    r_frame_rate: Option<String>,
+    pix_fmt: Option<String>,
+    nb_frames: Option<String>,
+    channels: Option<u32>,
+    channel_layout: Option<String>,
+    sample_rate: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+    #[serde(default)]
+    side_data_list: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct RawChapter {
+    start_time: Option<String>,
+    end_time: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+// "30000/1001" -> 29.97, "0/0" -> None
+fn parse_rational(value: &str) -> Option<f64> {
+    let (num, den) = value.split_once('/')?;
+    let num: f64 = num.parse().ok()?;
+    let den: f64 = den.parse().ok()?;
+
+    if den == 0.0 || num == 0.0 {
+        return None;
+    }
+
+    Some(num / den)
+}
+
+fn parse_num<T: std::str::FromStr>(value: &Option<String>) -> Option<T> {
+    value.as_deref().and_then(|v| v.parse().ok())
+}
+
+impl From<RawStream> for StreamInfo {
+    fn from(raw: RawStream) -> Self {
+        let kind = match raw.codec_type.as_deref() {
+            Some("video") => StreamKind::Video,
+            Some("audio") => StreamKind::Audio,
+            Some("subtitle") => StreamKind::Subtitle,
+            Some("data") => StreamKind::Data,
+            Some("attachment") => StreamKind::Attachment,
+            _ => StreamKind::Unknown,
+        };
+
+        // Newer FFmpeg versions report rotation in the display matrix side data, older ones in a tag
+        let rotation = raw
+            .side_data_list
+            .iter()
+            .find_map(|side| side.get("rotation").and_then(|r| r.as_i64()))
+            .map(|r| r as i32)
+            .or_else(|| raw.tags.get("rotate").and_then(|r| r.parse().ok()));
+
+        let frame_rate = raw
+            .avg_frame_rate
+            .as_deref()
+            .and_then(parse_rational)
+            .or_else(|| raw.r_frame_rate.as_deref().and_then(parse_rational));
+
+        Self {
+            index: raw.index,
+            kind,
+            codec: raw.codec_name,
+            profile: raw.profile,
+            bit_rate: parse_num(&raw.bit_rate),
+            language: raw.tags.get("language").cloned(),
+            title: raw.tags.get("title").cloned(),
+            width: raw.width,
+            height: raw.height,
+            frame_rate,
+            pixel_format: raw.pix_fmt,
+            rotation,
+            frames: parse_num(&raw.nb_frames),
+            channels: raw.channels,
+            channel_layout: raw.channel_layout,
+            sample_rate: parse_num(&raw.sample_rate),
+        }
+    }
+}
+
+impl From<RawProbe> for MediaInfo {
+    fn from(raw: RawProbe) -> Self {
+        let (attachment_streams, streams): (Vec<RawStream>, Vec<RawStream>) = raw
+            .streams
+            .into_iter()
+            .partition(|s| s.codec_type.as_deref() == Some("attachment"));
+
+        let attachments = attachment_streams
+            .into_iter()
+            .map(|s| AttachmentInfo {
+                file_name: s.tags.get("filename").cloned(),
+                mime_type: s.tags.get("mimetype").cloned(),
+            })
+            .collect();
+
+        let chapters = raw
+            .chapters
+            .into_iter()
+            .map(|c| ChapterInfo {
+                start: parse_num(&c.start_time).unwrap_or(0.0),
+                end: parse_num(&c.end_time).unwrap_or(0.0),
+                title: c.tags.get("title").cloned(),
+            })
+            .collect();
+
+        let format = raw.format;
+
+        Self {
+            container: format.as_ref().and_then(|f| f.format_name.clone()),
+            duration: format
+                .as_ref()
+                .and_then(|f| parse_num::<f64>(&f.duration))
+                .filter(|d| *d > 0.0),
+            bit_rate: format.as_ref().and_then(|f| parse_num(&f.bit_rate)),
+            size: format.as_ref().and_then(|f| parse_num(&f.size)),
+            streams: streams.into_iter().map(StreamInfo::from).collect(),
+            chapters,
+            attachments,
+        }
+    }
+}
+
+pub async fn probe(path: &str) -> Result<MediaInfo, Error> {
+    let ffprobe_path = get_ffprobe_path()?;
+
+    // Installations made before ffprobe was bundled only have ffmpeg
+    if !ffprobe_path.exists() {
+        return probe_with_ffmpeg(path).await;
+    }
+
+    let mut command = Command::new(&ffprobe_path);
+
+    command
+        .args([
+            "-v",
+            "error",
+            "-print_format",
+            "json",
+            "-show_format",
+            "-show_streams",
+            "-show_chapters",
+            path,
+        ])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    #[cfg(target_os = "windows")]
+    #[allow(unused_imports)]
+    {
+        use std::os::windows::process::CommandExt;
+        command.creation_flags(0x08000000);
+    }
+
+    let output = command.output().await?;
+
+    if !output.status.success() {
+        return Err(Error::ProbeFailed(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    let raw: RawProbe =
+        serde_json::from_slice(&output.stdout).map_err(|e| Error::ProbeFailed(e.to_string()))?;
+
+    Ok(MediaInfo::from(raw))
+}
+
+async fn probe_with_ffmpeg(path: &str) -> Result<MediaInfo, Error> {
+    let ffmpeg_path = get_ffmpeg_path()?;
+    let input = path.to_string();
+
+    // Waits for FFmpeg to exit, so it runs on a blocking thread instead of a runtime worker
+    let duration = tauri::async_runtime::spawn_blocking(move || {
+        get_video_duration_millis(ffmpeg_path, &input)
+    })
+    .await
+    .map_err(|e| Error::ProbeFailed(e.to_string()))?;
+
+    Ok(MediaInfo {
+        duration: (duration > 0.0).then_some(duration),
+        ..Default::default()
+    })
+}
+
+#[tauri::command]
+pub async fn probe_media(path: String) -> Result<MediaInfo, Error> {
+    probe(&path).await
+}
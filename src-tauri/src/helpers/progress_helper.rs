@@ -0,0 +1,325 @@
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::helpers::{ffmpeg_helper::time_to_seconds, probe_helper::MediaInfo};
+
+// Minimum time between two progress events of the same job
+const EMIT_INTERVAL: Duration = Duration::from_millis(250);
+
+// What the percentage is calculated from
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProgressMetric {
+    // Output time against the input duration
+    Time,
+    // Encoded frames against the frame count of the input
+    Frames,
+    // Output size against the input size, only close for stream copies but better than nothing
+    Bytes,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobProgress {
+    pub id: String,
+    // 0 - 100
+    pub progress: i32,
+    pub metric: ProgressMetric,
+    pub frame: Option<u64>,
+    pub fps: Option<f64>,
+    // Encoding speed relative to playback, 2.0 means two seconds of media per second
+    pub speed: Option<f64>,
+    // kbit/s
+    pub bitrate: Option<f64>,
+    // Bytes written so far
+    pub total_size: Option<u64>,
+    // Seconds of media written so far
+    pub out_time: Option<f64>,
+    // Seconds left
+    pub eta: Option<f64>,
+    // Estimated size of the finished file in bytes
+    pub projected_size: Option<u64>,
+}
+
+// Values of a single `-progress` block, FFmpeg ends every block with a `progress=` line
+#[derive(Default)]
+struct ProgressBlock {
+    frame: Option<u64>,
+    fps: Option<f64>,
+    speed: Option<f64>,
+    bitrate: Option<f64>,
+    total_size: Option<u64>,
+    out_time: Option<f64>,
+}
+
+pub struct ProgressTracker {
+    id: String,
+    duration: Option<f64>,
+    total_frames: Option<u64>,
+    input_size: Option<u64>,
+    block: ProgressBlock,
+    last_emit: Option<Instant>,
+}
+
+// FFmpeg writes "N/A" for values it doesn't know yet
+fn parse_value<T: std::str::FromStr>(value: &str) -> Option<T> {
+    value.trim().parse().ok()
+}
+
+impl ProgressTracker {
+    pub fn new(id: String, media: &MediaInfo) -> Self {
+        // Containers like mkv don't store the frame count, so it is estimated from the duration
+        let total_frames = media.first_video().and_then(|video| {
+            video.frames.or_else(|| {
+                let frames = media.duration? * video.frame_rate?;
+                Some(frames.round() as u64)
+            })
+        });
+
+        Self {
+            id,
+            duration: media.duration,
+            total_frames: total_frames.filter(|f| *f > 0),
+            input_size: media.size.filter(|s| *s > 0),
+            block: ProgressBlock::default(),
+            last_emit: None,
+        }
+    }
+
+    // Feeds one line of FFmpeg's `-progress` output. Returns a payload when a block is complete
+    // and enough time passed since the last one, the final block is always returned.
+    pub fn feed(&mut self, line: &str, elapsed: Duration) -> Option<JobProgress> {
+        let (key, value) = line.split_once('=')?;
+        let value = value.trim();
+
+        match key.trim() {
+            "frame" => self.block.frame = parse_value(value),
+            "fps" => self.block.fps = parse_value(value),
+            "speed" => self.block.speed = parse_value(value.trim_end_matches('x')),
+            "bitrate" => self.block.bitrate = parse_value(value.trim_end_matches("kbits/s")),
+            "total_size" => self.block.total_size = parse_value(value),
+            "out_time_us" => {
+                self.block.out_time = parse_value::<i64>(value)
+                    .filter(|us| *us >= 0)
+                    .map(|us| us as f64 / 1_000_000.0)
+            }
+            // Older FFmpeg versions only write out_time
+            "out_time" if self.block.out_time.is_none() => {
+                self.block.out_time = Some(time_to_seconds(value)).filter(|t| *t >= 0.0)
+            }
+            "progress" => {
+                let finished = value == "end";
+                let now = Instant::now();
+
+                let throttled = self
+                    .last_emit
+                    .is_some_and(|last| now.duration_since(last) < EMIT_INTERVAL);
+
+                let block = std::mem::take(&mut self.block);
+
+                if throttled && !finished {
+                    return None;
+                }
+
+                self.last_emit = Some(now);
+
+                return Some(self.build(block, finished, elapsed));
+            }
+            _ => (),
+        }
+
+        None
+    }
+
+    fn build(&self, block: ProgressBlock, finished: bool, elapsed: Duration) -> JobProgress {
+        let (fraction, metric) =
+            if let (Some(duration), Some(out_time)) = (self.duration, block.out_time) {
+                (Some(out_time / duration), ProgressMetric::Time)
+            } else if let (Some(total), Some(frame)) = (self.total_frames, block.frame) {
+                (Some(frame as f64 / total as f64), ProgressMetric::Frames)
+            } else if let (Some(input_size), Some(size)) = (self.input_size, block.total_size) {
+                // Never report a finished file from the size alone, the output may be larger than the input
+                (
+                    Some((size as f64 / input_size as f64).min(0.99)),
+                    ProgressMetric::Bytes,
+                )
+            } else {
+                (None, ProgressMetric::Unknown)
+            };
+
+        let fraction = if finished {
+            Some(1.0)
+        } else {
+            fraction.map(|f| f.clamp(0.0, 1.0))
+        };
+
+        let eta = match (fraction, metric, self.duration, block.out_time, block.speed) {
+            _ if finished => Some(0.0),
+            // Speed is the most stable estimate when the duration is known
+            (_, ProgressMetric::Time, Some(duration), Some(out_time), Some(speed))
+                if speed > 0.0 =>
+            {
+                Some(((duration - out_time) / speed).max(0.0))
+            }
+            (Some(f), _, _, _, _) if f > 0.0 => Some(elapsed.as_secs_f64() * (1.0 - f) / f),
+            _ => None,
+        };
+
+        let projected_size = match (fraction, block.total_size) {
+            (Some(f), Some(size)) if f > 0.0 => Some((size as f64 / f) as u64),
+            _ => None,
+        };
+
+        JobProgress {
+            id: self.id.clone(),
+            progress: fraction.map_or(0, |f| (f * 100.0) as i32),
+            metric,
+            frame: block.frame,
+            fps: block.fps,
+            speed: block.speed,
+            bitrate: block.bitrate,
+            total_size: block.total_size,
+            out_time: block.out_time,
+            eta,
+            projected_size,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::probe_helper::{StreamInfo, StreamKind};
+
+    fn stream(kind: StreamKind, codec: &str) -> StreamInfo {
+        StreamInfo {
+            index: 0,
+            kind,
+            codec: Some(codec.to_string()),
+            profile: None,
+            bit_rate: None,
+            language: None,
+            title: None,
+            width: None,
+            height: None,
+            frame_rate: None,
+            pixel_format: None,
+            rotation: None,
+            frames: None,
+            channels: None,
+            channel_layout: None,
+            sample_rate: None,
+        }
+    }
+
+    // Feeds a whole block and returns what the last line gave back
+    fn feed_block(tracker: &mut ProgressTracker, lines: &[&str]) -> Option<JobProgress> {
+        let elapsed = Duration::from_secs(4);
+
+        lines
+            .iter()
+            .map(|line| tracker.feed(line, elapsed))
+            .last()
+            .flatten()
+    }
+
+    fn tracker(media: MediaInfo) -> ProgressTracker {
+        ProgressTracker::new("job".into(), &media)
+    }
+
+    #[test]
+    fn reports_progress_from_the_output_time() {
+        let mut tracker = tracker(MediaInfo {
+            duration: Some(10.0),
+            ..MediaInfo::default()
+        });
+
+        assert!(tracker
+            .feed("out_time_us=5000000", Duration::ZERO)
+            .is_none());
+
+        let progress = feed_block(
+            &mut tracker,
+            &["speed=2.0x", "total_size=1000", "progress=continue"],
+        )
+        .unwrap();
+
+        assert_eq!(progress.progress, 50);
+        assert_eq!(progress.metric, ProgressMetric::Time);
+        assert_eq!(progress.speed, Some(2.0));
+        assert_eq!(progress.eta, Some(2.5));
+        assert_eq!(progress.projected_size, Some(2000));
+    }
+
+    #[test]
+    fn falls_back_to_frames() {
+        let mut video = stream(StreamKind::Video, "h264");
+        video.frames = Some(100);
+
+        let mut tracker = tracker(MediaInfo {
+            streams: vec![video],
+            ..MediaInfo::default()
+        });
+
+        let progress = feed_block(&mut tracker, &["frame=25", "progress=continue"]).unwrap();
+
+        assert_eq!(progress.progress, 25);
+        assert_eq!(progress.metric, ProgressMetric::Frames);
+        // 4 seconds for a quarter of the frames
+        assert_eq!(progress.eta, Some(12.0));
+    }
+
+    #[test]
+    fn size_never_reports_a_finished_file() {
+        let mut tracker = tracker(MediaInfo {
+            size: Some(1000),
+            ..MediaInfo::default()
+        });
+
+        let progress = feed_block(&mut tracker, &["total_size=5000", "progress=continue"]).unwrap();
+
+        assert_eq!(progress.progress, 99);
+        assert_eq!(progress.metric, ProgressMetric::Bytes);
+    }
+
+    #[test]
+    fn unknown_values_are_empty() {
+        let mut tracker = tracker(MediaInfo {
+            duration: Some(10.0),
+            ..MediaInfo::default()
+        });
+
+        let progress = feed_block(
+            &mut tracker,
+            &[
+                "out_time_us=N/A",
+                "speed=N/A",
+                "bitrate=N/A",
+                "progress=continue",
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(progress.progress, 0);
+        assert_eq!(progress.metric, ProgressMetric::Unknown);
+        assert_eq!(progress.speed, None);
+        assert_eq!(progress.eta, None);
+    }
+
+    #[test]
+    fn throttles_blocks_but_not_the_last_one() {
+        let mut tracker = tracker(MediaInfo {
+            duration: Some(10.0),
+            ..MediaInfo::default()
+        });
+
+        assert!(feed_block(&mut tracker, &["out_time_us=1000000", "progress=continue"]).is_some());
+        assert!(feed_block(&mut tracker, &["out_time_us=2000000", "progress=continue"]).is_none());
+
+        let last = feed_block(&mut tracker, &["out_time_us=3000000", "progress=end"]).unwrap();
+
+        assert_eq!(last.progress, 100);
+        assert_eq!(last.eta, Some(0.0));
+    }
+}
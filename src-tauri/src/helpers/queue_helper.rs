@@ -1,35 +1,217 @@
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::{
+    cmp::Reverse,
     collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
     sync::Arc,
 };
-use tauri::Emitter;
-use tokio::sync::{Mutex, Notify, Semaphore};
+use tokio::sync::{Mutex, Semaphore};
 use uuid::Uuid;
 
-use crate::tools::{app_handle::app, convert, error::Error};
+use crate::{
+    helpers::{
+        ffmpeg_helper::ConversionOptions,
+        history_helper::record_job,
+        journal_helper::{JobStatus, JournalEntry, QueueJournal},
+        output_helper::{
+            build_output_path, default_output_path, normalize_path, resolve_output_path,
+            CollisionPolicy, OutputResolution, OutputRule,
+        },
+        preset_helper::find_preset,
+        settings_helper::load_settings,
+    },
+    tools::{
+        convert::{self, ConversionReport},
+        engine::emit,
+        error::Error,
+        job_control::JobControl,
+    },
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
 
 // Job object for each file
-struct PipelineJob {
+pub struct PipelineJob {
     pub id: Uuid,
     pub path: String,
     pub extension: String,
     pub category: String,
-    //pub cancel_notifier: Arc<Notify> (Notifier created while process, to decrease the RAM usage)
+    pub priority: JobPriority,
+    pub preset: Option<String>,
+    pub options: ConversionOptions,
+    // Output path from the output rule, before the collision policy was applied
+    pub target_path: String,
+    // Final output path, settled against existing files and other jobs when the job is queued
+    pub output_path: String,
+    // Overrides the collision policy from the settings
+    pub collision: Option<CollisionPolicy>,
+    //pub control: Arc<JobControl> (Control created while process, to decrease the RAM usage)
 }
 
-#[derive(serde::Deserialize)]
+impl PipelineJob {
+    // A job referencing a preset is converted to the container of that preset
+    pub fn from_request(job: JobRequest) -> Result<Self, Error> {
+        // The id comes from the frontend, a malformed one is rejected instead of panicking the command
+        let id = Uuid::parse_str(&job.id).map_err(|_| Error::JobNotFound)?;
+
+        let extension = match &job.preset {
+            Some(name) => {
+                find_preset(name)
+                    .ok_or_else(|| Error::PresetNotFound(name.clone()))?
+                    .container
+            }
+            None => job.extension,
+        };
+
+        let output_path = default_output_path(&job.path, &extension)?
+            .to_string_lossy()
+            .to_string();
+
+        Ok(Self {
+            id,
+            path: job.path,
+            extension,
+            category: job.category,
+            priority: job.priority,
+            preset: job.preset,
+            options: job.options,
+            target_path: output_path.clone(),
+            output_path,
+            collision: job.collision,
+        })
+    }
+
+    fn from_entry(entry: JournalEntry) -> Option<Self> {
+        // Entries written before output paths were stored get the old default path
+        let output_path = match entry.output_path {
+            Some(output_path) => output_path,
+            None => default_output_path(&entry.path, &entry.extension)
+                .ok()?
+                .to_string_lossy()
+                .to_string(),
+        };
+
+        let target_path = entry.target_path.unwrap_or_else(|| output_path.clone());
+
+        Some(Self {
+            id: Uuid::parse_str(&entry.id).ok()?,
+            path: entry.path,
+            extension: entry.extension,
+            category: entry.category,
+            priority: entry.priority,
+            preset: entry.preset,
+            options: entry.options,
+            target_path,
+            output_path,
+            collision: entry.collision,
+        })
+    }
+
+    fn journal_entry(&self) -> JournalEntry {
+        JournalEntry {
+            priority: self.priority,
+            preset: self.preset.clone(),
+            options: self.options.clone(),
+            target_path: Some(self.target_path.clone()),
+            output_path: Some(self.output_path.clone()),
+            collision: self.collision,
+            ..JournalEntry::new(
+                self.id.to_string(),
+                self.path.clone(),
+                self.extension.clone(),
+                self.category.clone(),
+            )
+        }
+    }
+
+    // The output rule is rendered before the job is queued, the collision policy is applied afterwards
+    pub async fn apply_output_rule(
+        &mut self,
+        rule: &OutputRule,
+        index: usize,
+    ) -> Result<(), Error> {
+        let target_path = build_output_path(
+            &self.path,
+            &self.extension,
+            self.preset.as_deref(),
+            &self.options,
+            rule,
+            index,
+        )
+        .await?;
+
+        self.target_path = target_path.to_string_lossy().to_string();
+        self.output_path = self.target_path.clone();
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
 pub struct JobRequest {
     pub id: String,
     pub path: String,
     pub extension: String,
     pub category: String,
+    #[serde(default)]
+    pub priority: JobPriority,
+    #[serde(default)]
+    pub preset: Option<String>,
+    #[serde(default)]
+    pub options: ConversionOptions,
+    #[serde(default)]
+    pub collision: Option<CollisionPolicy>,
+    // Overrides the output rule from the settings
+    #[serde(default)]
+    pub output: Option<OutputRule>,
+}
+
+// What the user picked when asked about an output that is already taken
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CollisionAnswer {
+    Overwrite,
+    // Keep both files, the new one gets a suffix
+    Rename,
+    Skip,
+}
+
+impl CollisionAnswer {
+    fn policy(self) -> CollisionPolicy {
+        match self {
+            CollisionAnswer::Overwrite => CollisionPolicy::Overwrite,
+            CollisionAnswer::Rename => CollisionPolicy::AutoSuffix,
+            CollisionAnswer::Skip => CollisionPolicy::Skip,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct QueueOrderItem {
+    id: String,
+    priority: JobPriority,
+}
+
+struct RunningJob {
+    control: Arc<JobControl>,
+    output_path: String,
 }
 
 struct PipelineState {
     queue: Mutex<VecDeque<PipelineJob>>,
-    running_jobs: Mutex<HashMap<Uuid, Arc<Notify>>>,
+    running_jobs: Mutex<HashMap<Uuid, RunningJob>>,
+    // Jobs of the "ask" policy whose output was taken, they wait for resolve_collision
+    collisions: Mutex<HashMap<Uuid, PipelineJob>>,
     current_limit: Mutex<usize>,
+    journal: QueueJournal,
 }
 
 #[derive(Clone)]
@@ -40,54 +222,112 @@ pub struct PipelineManager {
 
 impl PipelineManager {
     pub fn new(max_concurrency: usize) -> Self {
+        let journal = QueueJournal::load();
+
+        // Putting the jobs that were still waiting when the app was closed back in the queue
+        let restored: VecDeque<PipelineJob> = journal
+            .restore()
+            .into_iter()
+            .filter_map(PipelineJob::from_entry)
+            .collect();
+
         Self {
             inner: Arc::new(PipelineState {
-                queue: Mutex::new(VecDeque::new()),
+                queue: Mutex::new(restored),
                 running_jobs: Mutex::new(HashMap::new()),
+                collisions: Mutex::new(HashMap::new()),
                 current_limit: Mutex::new(max_concurrency),
+                journal,
             }),
             semaphore: Arc::new(Semaphore::new(max_concurrency)),
         }
     }
 
-    pub async fn add_job(&self, job: JobRequest) -> Result<String, std::io::Error> {
-        let id = Uuid::parse_str(&job.id).unwrap();
+    // A skipped job is not queued, but it is not an error either. Neither is a job that waits for the
+    // user to decide about a taken output.
+    pub async fn add_job(&self, job: JobRequest) -> Result<String, Error> {
+        let settings = load_settings();
+        let rule = job.output.clone().unwrap_or(settings.output);
 
-        let job = PipelineJob {
-            id,
-            path: job.path,
-            extension: job.extension,
-            category: job.category,
+        let mut job = PipelineJob::from_request(job)?;
+        let id = job.id;
+
+        job.apply_output_rule(&rule, 1).await?;
+
+        let policy = settings.collision_policy;
+
+        // The queue stays locked until the job is in it, so no other job can take the same output
+        let mut queue = self.inner.queue.lock().await;
+        let mut reserved = self.reserved_outputs(&queue).await;
+
+        let Some(job) = self.settle(job, policy, &mut reserved).await? else {
+            return Ok(id.to_string());
         };
 
-        {
-            let mut queue = self.inner.queue.lock().await;
-            queue.push_back(job);
-        }
+        self.inner.journal.record(vec![job.journal_entry()]);
+
+        let output_path = job.output_path.clone();
+        queue.push_back(job);
 
-        let _ = app().emit("job-queued", json!({ "id": id.to_string() }));
+        emit(
+            "job-queued",
+            json!({ "id": id.to_string(), "output_path": output_path }),
+        );
 
         Ok(id.to_string())
     }
 
-    pub async fn add_jobs(&self, jobs: Vec<JobRequest>) {
+    // Jobs that collide under the "ask" policy are reported with an event and the rest of the batch is queued
+    pub async fn add_jobs(&self, requests: Vec<JobRequest>) -> Result<(), Error> {
+        let settings = load_settings();
+
+        let mut jobs = Vec::with_capacity(requests.len());
+
+        // {index} in the name template is the position of the file in the batch
+        for (index, request) in requests.into_iter().enumerate() {
+            let rule = request
+                .output
+                .clone()
+                .unwrap_or_else(|| settings.output.clone());
+
+            let mut job = PipelineJob::from_request(request)?;
+            job.apply_output_rule(&rule, index + 1).await?;
+
+            jobs.push(job);
+        }
+
+        let policy = settings.collision_policy;
+
         let mut queue = self.inner.queue.lock().await;
+        let mut reserved = self.reserved_outputs(&queue).await;
+
+        let mut accepted = Vec::with_capacity(jobs.len());
 
         for job in jobs {
-            let id = Uuid::parse_str(&job.id).unwrap();
+            accepted.extend(self.settle(job, policy, &mut reserved).await?);
+        }
 
-            queue.push_back(PipelineJob {
-                id,
-                path: job.path,
-                extension: job.extension,
-                category: job.category,
-            });
+        // Single journal write for the whole batch
+        self.inner
+            .journal
+            .record(accepted.iter().map(|job| job.journal_entry()).collect());
+
+        for job in accepted {
+            let id = job.id;
+            let output_path = job.output_path.clone();
 
-            let _ = app().emit("job-queued", json!({ "id": id.to_string() }));
+            queue.push_back(job);
+
+            emit(
+                "job-queued",
+                json!({ "id": id.to_string(), "output_path": output_path }),
+            );
         }
 
         drop(queue);
         self.try_dispatch();
+
+        Ok(())
     }
 
     pub async fn cancel_job(&self, id: String) -> Result<(), Error> {
@@ -97,8 +337,8 @@ impl PipelineManager {
         {
             let running = self.inner.running_jobs.lock().await;
 
-            if let Some(notify) = running.get(&job_id) {
-                notify.notify_one();
+            if let Some(job) = running.get(&job_id) {
+                job.control.cancel();
                 return Ok(());
             }
         }
@@ -108,32 +348,179 @@ impl PipelineManager {
             let mut queue = self.inner.queue.lock().await;
 
             if let Some(pos) = queue.iter().position(|job| job.id == job_id) {
-                queue.remove(pos);
+                let job = queue.remove(pos);
+
+                self.inner
+                    .journal
+                    .set_status(&job_id.to_string(), JobStatus::Cancelled, None);
+
+                if let Some(job) = job {
+                    record_job(
+                        &job,
+                        &ConversionReport::default(),
+                        &Err(Error::ConversionCancelled),
+                    );
+                }
 
-                let _ = app().emit("job-cancelled", json!({ "id": job_id.to_string() }));
+                emit("job-cancelled", json!({ "id": job_id.to_string() }));
                 return Ok(());
             }
         }
 
+        // Last, a job that still waits for the answer to a collision prompt
+        if self.inner.collisions.lock().await.remove(&job_id).is_some() {
+            emit("job-cancelled", json!({ "id": job_id.to_string() }));
+            return Ok(());
+        }
+
         Err(Error::JobNotFound)
     }
 
     pub async fn cancel_all(&self) {
+        self.inner.collisions.lock().await.clear();
+
         {
             let mut queue = self.inner.queue.lock().await;
 
-            queue.clear();
+            let ids: Vec<String> = queue.iter().map(|job| job.id.to_string()).collect();
+            self.inner
+                .journal
+                .set_status_many(&ids, JobStatus::Cancelled);
+
+            for job in queue.drain(..) {
+                record_job(
+                    &job,
+                    &ConversionReport::default(),
+                    &Err(Error::ConversionCancelled),
+                );
+            }
         }
 
         {
             let running = self.inner.running_jobs.lock().await;
 
-            for (_, notify) in running.iter() {
-                notify.notify_one();
+            for (_, job) in running.iter() {
+                job.control.cancel();
             }
         }
 
-        let _ = app().emit("jobs-cancelled", true);
+        emit("jobs-cancelled", true);
+    }
+
+    // Suspends the FFmpeg process of a running job, the job keeps its slot while paused
+    pub async fn pause_job(&self, id: String) -> Result<(), Error> {
+        self.set_job_paused(id, true).await
+    }
+
+    pub async fn resume_job(&self, id: String) -> Result<(), Error> {
+        self.set_job_paused(id, false).await
+    }
+
+    async fn set_job_paused(&self, id: String, paused: bool) -> Result<(), Error> {
+        let job_id = Uuid::parse_str(&id).map_err(|_| Error::JobNotFound)?;
+
+        let running = self.inner.running_jobs.lock().await;
+        let job = running.get(&job_id).ok_or(Error::JobNotFound)?;
+
+        job.control.set_paused(paused);
+
+        Ok(())
+    }
+
+    pub async fn pause_all(&self) {
+        self.set_all_paused(true).await;
+    }
+
+    pub async fn resume_all(&self) {
+        self.set_all_paused(false).await;
+    }
+
+    async fn set_all_paused(&self, paused: bool) {
+        let running = self.inner.running_jobs.lock().await;
+
+        for (_, job) in running.iter() {
+            job.control.set_paused(paused);
+        }
+    }
+
+    // Moves a queued job to the given position of the order reported by `queue-order`,
+    // positions past the end put it at the back
+    pub async fn move_job(&self, id: String, position: usize) -> Result<(), Error> {
+        let job_id = Uuid::parse_str(&id).map_err(|_| Error::JobNotFound)?;
+
+        let mut queue = self.inner.queue.lock().await;
+
+        if let Some(priority) = move_queued_job(&mut queue, job_id, position)? {
+            self.inner.journal.set_priority(&id, priority);
+        }
+
+        self.queue_order_changed(&queue);
+
+        Ok(())
+    }
+
+    pub async fn move_job_to_front(&self, id: String) -> Result<(), Error> {
+        self.move_job(id, 0).await
+    }
+
+    pub async fn move_job_to_back(&self, id: String) -> Result<(), Error> {
+        self.move_job(id, usize::MAX).await
+    }
+
+    pub async fn set_job_priority(&self, id: String, priority: JobPriority) -> Result<(), Error> {
+        let job_id = Uuid::parse_str(&id).map_err(|_| Error::JobNotFound)?;
+
+        let mut queue = self.inner.queue.lock().await;
+
+        let job = queue
+            .iter_mut()
+            .find(|job| job.id == job_id)
+            .ok_or(Error::JobNotFound)?;
+        job.priority = priority;
+
+        self.inner.journal.set_priority(&id, priority);
+        self.queue_order_changed(&queue);
+
+        Ok(())
+    }
+
+    // Queued jobs in the order they will be started
+    pub async fn queue_order(&self) -> Vec<String> {
+        let queue = self.inner.queue.lock().await;
+
+        dispatch_order(&queue)
+            .into_iter()
+            .map(|index| queue[index].id.to_string())
+            .collect()
+    }
+
+    fn queue_order_changed(&self, queue: &VecDeque<PipelineJob>) {
+        let ids: Vec<String> = queue.iter().map(|job| job.id.to_string()).collect();
+        self.inner.journal.set_order(&ids);
+
+        let order: Vec<QueueOrderItem> = dispatch_order(queue)
+            .into_iter()
+            .map(|index| QueueOrderItem {
+                id: queue[index].id.to_string(),
+                priority: queue[index].priority,
+            })
+            .collect();
+
+        emit("queue-order", order);
+    }
+
+    // Starts the jobs restored from the journal, they were queued before anything added in this session.
+    // Nothing is emitted when there were none, the frontend would read it as a finished queue.
+    pub fn resume_restored_jobs(&self) {
+        let manager = self.clone();
+
+        tauri::async_runtime::spawn(async move {
+            let restored = !manager.inner.queue.lock().await.is_empty();
+
+            if restored {
+                manager.dispatch_loop().await;
+            }
+        });
     }
 
     pub fn try_dispatch(&self) {
@@ -152,49 +539,78 @@ impl PipelineManager {
                 Err(_) => break,
             };
 
-            // Then checking for available jobs
+            let control = Arc::new(JobControl::new());
+
+            // Then checking for available jobs, highest priority first and queue order within the same priority.
+            // The job is moved to the running jobs before the queue is unlocked, so its output path stays reserved.
             let job: Option<PipelineJob> = {
                 let mut queue = self.inner.queue.lock().await;
-                queue.pop_front()
+                let job = next_job_index(&queue).and_then(|index| queue.remove(index));
+
+                if let Some(job) = &job {
+                    let mut running = self.inner.running_jobs.lock().await;
+
+                    running.insert(
+                        job.id,
+                        RunningJob {
+                            control: control.clone(),
+                            output_path: job.output_path.clone(),
+                        },
+                    );
+                }
+
+                job
             };
 
             match job {
                 Some(job) => {
                     // If both are true
-                    let cancel_notify = Arc::new(Notify::new()); // Line 29
-
-                    {
-                        let mut running = self.inner.running_jobs.lock().await;
-
-                        running.insert(job.id, cancel_notify.clone());
-                    }
-
                     let manager_clone = self.clone();
-                    let notify_clone = cancel_notify.clone();
+
+                    self.inner
+                        .journal
+                        .set_status(&job.id.to_string(), JobStatus::Running, None);
 
                     tauri::async_runtime::spawn(async move {
                         let _permit = permit;
 
-                        let _ = app().emit("job-started", json!({ "id": job.id.to_string() }));
-                        let result = convert::exec_conversion(
-                            job.id,
-                            &job.path,
-                            &job.extension,
-                            &job.category,
-                            &notify_clone,
-                        )
-                        .await;
+                        emit("job-started", json!({ "id": job.id.to_string() }));
+                        let mut report = ConversionReport::default();
+                        let result = convert::exec_conversion(&job, &control, &mut report).await;
+
+                        record_job(&job, &report, &result);
+
+                        let journal = &manager_clone.inner.journal;
 
                         match result {
-                            Ok(_) => (),
+                            Ok(_) => {
+                                journal.set_status(&job.id.to_string(), JobStatus::Completed, None)
+                            }
                             Err(Error::ConversionCancelled) => {
-                                let _ = app()
-                                    .emit("job-cancelled", json!({ "id": job.id.to_string() }));
+                                journal.set_status(&job.id.to_string(), JobStatus::Cancelled, None);
+
+                                emit("job-cancelled", json!({ "id": job.id.to_string() }));
                             }
                             Err(e) => {
-                                let _ = app().emit(
+                                journal.set_status(
+                                    &job.id.to_string(),
+                                    JobStatus::Failed,
+                                    Some(e.to_string()),
+                                );
+
+                                // FFmpeg failures carry their kind and advice for the frontend
+                                let failure = match &e {
+                                    Error::FfmpegFailed(failure) => Some(failure),
+                                    _ => None,
+                                };
+
+                                emit(
                                     "job-failed",
-                                    json!({ "id": job.id.to_string(), "error": e.to_string() }),
+                                    json!({
+                                        "id": job.id.to_string(),
+                                        "error": e.to_string(),
+                                        "failure": failure,
+                                    }),
                                 );
                             }
                         }
@@ -211,13 +627,120 @@ impl PipelineManager {
                 }
                 None => {
                     drop(permit);
-                    let _ = app().emit("all-jobs-completed", true);
+                    emit("all-jobs-completed", true);
                     break;
                 }
             }
         }
     }
 
+    pub fn journal(&self) -> Vec<JournalEntry> {
+        self.inner.journal.entries()
+    }
+
+    pub fn clear_journal(&self) {
+        self.inner.journal.clear_finished();
+    }
+
+    // Puts an interrupted or failed job from the journal back in the queue
+    pub async fn requeue_job(&self, id: String) -> Result<(), Error> {
+        let entry = self.inner.journal.get(&id).ok_or(Error::JobNotFound)?;
+
+        if !matches!(
+            entry.status,
+            JobStatus::Interrupted | JobStatus::Failed | JobStatus::Cancelled
+        ) {
+            return Err(Error::JobNotFound);
+        }
+
+        let job = PipelineJob::from_entry(entry).ok_or(Error::JobNotFound)?;
+
+        // The output is settled again, something else may have been written there in the meantime
+        let policy = load_settings().collision_policy;
+
+        self.queue_settled(job, policy).await
+    }
+
+    // Answers the prompt of a job that waits because its output was taken
+    pub async fn resolve_collision(
+        &self,
+        id: String,
+        answer: CollisionAnswer,
+    ) -> Result<(), Error> {
+        let job_id = Uuid::parse_str(&id).map_err(|_| Error::JobNotFound)?;
+
+        let mut job = self
+            .inner
+            .collisions
+            .lock()
+            .await
+            .remove(&job_id)
+            .ok_or(Error::JobNotFound)?;
+
+        // The answer replaces the policy of the job, so it is kept if the job is requeued later
+        let policy = answer.policy();
+        job.collision = Some(policy);
+
+        self.queue_settled(job, policy).await
+    }
+
+    // Settles the output of a single job, queues it and starts the queue
+    async fn queue_settled(&self, job: PipelineJob, policy: CollisionPolicy) -> Result<(), Error> {
+        let mut queue = self.inner.queue.lock().await;
+        let mut reserved = self.reserved_outputs(&queue).await;
+
+        let Some(job) = self.settle(job, policy, &mut reserved).await? else {
+            return Ok(());
+        };
+
+        self.inner.journal.record(vec![job.journal_entry()]);
+
+        let id = job.id.to_string();
+        let output_path = job.output_path.clone();
+        queue.push_back(job);
+
+        emit(
+            "job-queued",
+            json!({ "id": id, "output_path": output_path }),
+        );
+
+        drop(queue);
+        self.try_dispatch();
+
+        Ok(())
+    }
+
+    // Applies the collision policy to a job that is about to be queued. Returns None if it was skipped,
+    // or if the user is asked about it, then it waits in `collisions` until the answer comes.
+    async fn settle(
+        &self,
+        mut job: PipelineJob,
+        policy: CollisionPolicy,
+        reserved: &mut Vec<PathBuf>,
+    ) -> Result<Option<PipelineJob>, Error> {
+        match settle_output(&mut job, policy, reserved) {
+            Ok(true) => Ok(Some(job)),
+            Ok(false) => Ok(None),
+            Err(Error::OutputExists(_)) => {
+                self.inner.collisions.lock().await.insert(job.id, job);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    // Normalized output paths of the queued and running jobs
+    async fn reserved_outputs(&self, queue: &VecDeque<PipelineJob>) -> Vec<PathBuf> {
+        let running = self.inner.running_jobs.lock().await;
+
+        queue
+            .iter()
+            .map(|job| job.output_path.as_str())
+            .chain(running.values().map(|job| job.output_path.as_str()))
+            .map(|path| normalize_path(Path::new(path)))
+            .collect()
+    }
+
     pub async fn set_concurrency(&self, new_limit: usize) {
         let mut current_limit = self.inner.current_limit.lock().await;
 
@@ -241,3 +764,186 @@ impl PipelineManager {
         *current_limit = new_limit;
     }
 }
+
+// Applies the collision policy to a new job. Returns false if the job should be skipped,
+// and OutputExists if the user has to decide what happens with it.
+pub fn settle_output(
+    job: &mut PipelineJob,
+    policy: CollisionPolicy,
+    reserved: &mut Vec<PathBuf>,
+) -> Result<bool, Error> {
+    let policy = job.collision.unwrap_or(policy);
+    let candidate = PathBuf::from(&job.target_path);
+
+    let resolution = resolve_output_path(&job.path, candidate, policy, reserved);
+
+    match resolution {
+        Ok(OutputResolution::Path(path)) => {
+            reserved.push(normalize_path(&path));
+            job.output_path = path.to_string_lossy().to_string();
+
+            Ok(true)
+        }
+        Ok(OutputResolution::Skip(path)) => {
+            emit(
+                "job-skipped",
+                json!({ "id": job.id.to_string(), "path": job.path, "output_path": path }),
+            );
+
+            Ok(false)
+        }
+        Err(e) => {
+            emit(
+                "job-collision",
+                json!({ "id": job.id.to_string(), "path": job.path, "output_path": job.target_path }),
+            );
+
+            Err(e)
+        }
+    }
+}
+
+// Moves a job to `position` in dispatch order. A job moved between jobs of another priority takes their
+// priority, otherwise it would jump back to its own band. Returns the new priority if it changed.
+fn move_queued_job(
+    queue: &mut VecDeque<PipelineJob>,
+    job_id: Uuid,
+    position: usize,
+) -> Result<Option<JobPriority>, Error> {
+    // Putting the queue in dispatch order first, so positions mean the same here and in the frontend.
+    // The sort is stable, so the order within a priority doesn't change.
+    queue
+        .make_contiguous()
+        .sort_by_key(|job| Reverse(job.priority));
+
+    let current = queue
+        .iter()
+        .position(|job| job.id == job_id)
+        .ok_or(Error::JobNotFound)?;
+    let mut job = queue.remove(current).ok_or(Error::JobNotFound)?;
+
+    let position = position.min(queue.len());
+
+    let upper = position
+        .checked_sub(1)
+        .map_or(JobPriority::High, |index| queue[index].priority);
+    let lower = queue
+        .get(position)
+        .map_or(JobPriority::Low, |next| next.priority);
+
+    let priority = job.priority.clamp(lower, upper);
+    let changed = (priority != job.priority).then_some(priority);
+
+    job.priority = priority;
+    queue.insert(position, job);
+
+    Ok(changed)
+}
+
+// Index of the job that should be started next
+fn next_job_index(queue: &VecDeque<PipelineJob>) -> Option<usize> {
+    let mut best: Option<usize> = None;
+
+    for (index, job) in queue.iter().enumerate() {
+        match best {
+            Some(b) if queue[b].priority >= job.priority => (),
+            _ => best = Some(index),
+        }
+    }
+
+    best
+}
+
+// Queue indexes sorted by priority, a stable sort keeps the queue order within the same priority
+fn dispatch_order(queue: &VecDeque<PipelineJob>) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..queue.len()).collect();
+    order.sort_by(|a, b| queue[*b].priority.cmp(&queue[*a].priority));
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(priorities: &[JobPriority]) -> VecDeque<PipelineJob> {
+        priorities
+            .iter()
+            .map(|priority| PipelineJob {
+                id: Uuid::new_v4(),
+                path: "/videos/clip.mp4".into(),
+                extension: "mkv".into(),
+                category: "video".into(),
+                priority: *priority,
+                preset: None,
+                options: ConversionOptions::default(),
+                target_path: "/videos/clip.mkv".into(),
+                output_path: "/videos/clip.mkv".into(),
+                collision: None,
+            })
+            .collect()
+    }
+
+    fn ids(queue: &VecDeque<PipelineJob>) -> Vec<Uuid> {
+        queue.iter().map(|job| job.id).collect()
+    }
+
+    #[test]
+    fn dispatch_order_follows_priority_then_queue_order() {
+        use JobPriority::*;
+
+        let queue = queue(&[Normal, High, Low, High, Normal]);
+
+        assert_eq!(dispatch_order(&queue), [1, 3, 0, 4, 2]);
+        assert_eq!(next_job_index(&queue), Some(1));
+        assert_eq!(next_job_index(&VecDeque::new()), None);
+    }
+
+    #[test]
+    fn moving_within_a_priority_keeps_it() {
+        let mut queue = queue(&[JobPriority::Normal, JobPriority::Normal]);
+        let [a, b] = [queue[0].id, queue[1].id];
+
+        assert_eq!(move_queued_job(&mut queue, b, 0).unwrap(), None);
+        assert_eq!(ids(&queue), [b, a]);
+    }
+
+    #[test]
+    fn moved_job_takes_the_priority_of_its_neighbours() {
+        use JobPriority::*;
+
+        let mut queue = queue(&[High, Low, Normal]);
+        let [a, b, c] = [queue[0].id, queue[1].id, queue[2].id];
+
+        // Dispatch order is a, c, b, so b ends up between a high and a normal job
+        assert_eq!(move_queued_job(&mut queue, b, 1).unwrap(), Some(Normal));
+        assert_eq!(ids(&queue), [a, b, c]);
+
+        assert_eq!(move_queued_job(&mut queue, c, 0).unwrap(), Some(High));
+        assert_eq!(ids(&queue), [c, a, b]);
+    }
+
+    #[test]
+    fn moving_past_the_end_puts_the_job_last() {
+        use JobPriority::*;
+
+        let mut queue = queue(&[High, Normal, Low]);
+        let [a, b, c] = [queue[0].id, queue[1].id, queue[2].id];
+
+        assert_eq!(
+            move_queued_job(&mut queue, a, usize::MAX).unwrap(),
+            Some(Low)
+        );
+        assert_eq!(ids(&queue), [b, c, a]);
+        assert_eq!(dispatch_order(&queue), [0, 1, 2]);
+    }
+
+    #[test]
+    fn moving_an_unknown_job_fails() {
+        let mut queue = queue(&[JobPriority::Normal]);
+
+        assert!(matches!(
+            move_queued_job(&mut queue, Uuid::new_v4(), 0),
+            Err(Error::JobNotFound)
+        ));
+    }
+}
@@ -0,0 +1,300 @@
+use std::path::Path;
+
+use crate::helpers::{
+    ffmpeg_helper::ConversionOptions,
+    preset_helper::Preset,
+    probe_helper::{MediaInfo, StreamInfo},
+};
+
+// Which streams can be copied into the output as they are, without re-encoding
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StreamCopy {
+    pub video: bool,
+    pub audio: bool,
+}
+
+impl StreamCopy {
+    pub fn any(&self) -> bool {
+        self.video || self.audio
+    }
+}
+
+// Codecs (as reported by ffprobe) that can be stored in each container without re-encoding
+fn container_codecs(container: &str) -> Option<(&'static [&'static str], &'static [&'static str])> {
+    let codecs: (&[&str], &[&str]) = match container {
+        "mp4" => (
+            &["h264", "hevc", "av1", "vp9", "mpeg4"],
+            &["aac", "mp3", "ac3", "eac3", "opus", "alac", "flac"],
+        ),
+        "mov" => (
+            &["h264", "hevc", "prores", "mpeg4", "mjpeg"],
+            &["aac", "mp3", "alac", "ac3", "pcm_s16le", "pcm_s24le"],
+        ),
+        "mkv" => (
+            &[
+                "h264",
+                "hevc",
+                "av1",
+                "vp8",
+                "vp9",
+                "mpeg4",
+                "mpeg2video",
+                "prores",
+                "mjpeg",
+                "theora",
+            ],
+            &[
+                "aac",
+                "mp3",
+                "ac3",
+                "eac3",
+                "dts",
+                "opus",
+                "vorbis",
+                "flac",
+                "alac",
+                "truehd",
+                "pcm_s16le",
+                "pcm_s24le",
+            ],
+        ),
+        "webm" => (&["vp8", "vp9", "av1"], &["opus", "vorbis"]),
+        "avi" => (
+            &["mpeg4", "h264", "mjpeg", "msmpeg4v3"],
+            &["mp3", "ac3", "pcm_s16le"],
+        ),
+        "flv" => (&["h264", "flv1"], &["aac", "mp3"]),
+        "mp3" => (&[], &["mp3"]),
+        "aac" => (&[], &["aac"]),
+        "flac" => (&[], &["flac"]),
+        "wav" => (&[], &["pcm_s16le", "pcm_s24le", "pcm_f32le"]),
+        "ogg" => (&[], &["opus", "vorbis", "flac"]),
+        _ => return None,
+    };
+
+    Some(codecs)
+}
+
+// Codec written by an FFmpeg encoder, used to check if a preset asks for the codec the input already has
+fn encoder_codec(encoder: &str) -> &str {
+    match encoder {
+        "libx264" => "h264",
+        "libx265" => "hevc",
+        "libvpx-vp9" => "vp9",
+        "libsvtav1" | "libaom-av1" => "av1",
+        "libopus" => "opus",
+        "libvorbis" => "vorbis",
+        "libmp3lame" => "mp3",
+        "msmpeg4" => "msmpeg4v3",
+        other => other,
+    }
+}
+
+fn stream_codec(stream: Option<&StreamInfo>) -> Option<&str> {
+    stream.and_then(|s| s.codec.as_deref())
+}
+
+// Decides per stream whether the input can be remuxed into `container` instead of being re-encoded.
+// Streams are only copied if the job doesn't ask for anything that needs a re-encode.
+pub fn plan_stream_copy(
+    input: &str,
+    container: &str,
+    media: &MediaInfo,
+    preset: &Preset,
+    options: &ConversionOptions,
+) -> StreamCopy {
+    let Some((video_codecs, audio_codecs)) = container_codecs(container) else {
+        return StreamCopy::default();
+    };
+
+    // Converting to the same container is a request to re-encode, copying would only duplicate the file
+    let same_container = Path::new(input)
+        .extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case(container));
+
+    if same_container && !options.remux {
+        return StreamCopy::default();
+    }
+
+    // Extra arguments are meant for the encoder, they can't be applied to copied streams
+    if !media.streams_known() || !preset.extra_args.is_empty() {
+        return StreamCopy::default();
+    }
+
+    // Built-in presets only describe the default codecs, any codec the input already has is fine for them
+    let (preset_video, preset_audio) = if preset.builtin {
+        (None, None)
+    } else {
+        (
+            preset.video_codec.as_deref(),
+            Some(preset.audio_codec.as_str()),
+        )
+    };
+
+    let video = stream_codec(media.first_video()).is_some_and(|codec| {
+        let wants_other_codec = options
+            .video_codec
+            .as_deref()
+            .or(preset_video)
+            .is_some_and(|encoder| encoder_codec(encoder) != codec);
+
+        let transforms = options.crf.is_some()
+            || options.video_bitrate.is_some()
+            || options.width.is_some()
+            || options.height.is_some()
+            || options.frame_rate.is_some()
+            || preset.quality.is_some();
+
+        !preset.disable_video && !wants_other_codec && !transforms && video_codecs.contains(&codec)
+    });
+
+    let audio = stream_codec(media.audio_streams().next()).is_some_and(|codec| {
+        let wants_other_codec = options
+            .audio_codec
+            .as_deref()
+            .or(preset_audio)
+            .is_some_and(|encoder| encoder_codec(encoder) != codec);
+
+        let transforms = options.audio_bitrate.is_some() || options.sample_rate.is_some();
+
+        !wants_other_codec && !transforms && audio_codecs.contains(&codec)
+    });
+
+    StreamCopy { video, audio }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::probe_helper::StreamKind;
+
+    fn stream(kind: StreamKind, codec: &str) -> StreamInfo {
+        StreamInfo {
+            index: 0,
+            kind,
+            codec: Some(codec.to_string()),
+            profile: None,
+            bit_rate: None,
+            language: None,
+            title: None,
+            width: None,
+            height: None,
+            frame_rate: None,
+            pixel_format: None,
+            rotation: None,
+            frames: None,
+            channels: None,
+            channel_layout: None,
+            sample_rate: None,
+        }
+    }
+
+    fn media(video: &str, audio: &str) -> MediaInfo {
+        MediaInfo {
+            streams: vec![
+                stream(StreamKind::Video, video),
+                stream(StreamKind::Audio, audio),
+            ],
+            ..MediaInfo::default()
+        }
+    }
+
+    fn preset(builtin: bool) -> Preset {
+        Preset {
+            name: "mp4".into(),
+            container: "mp4".into(),
+            video_codec: Some("libx264".into()),
+            audio_codec: "aac".into(),
+            disable_video: false,
+            quality: None,
+            extra_args: Vec::new(),
+            hwaccel_supported: true,
+            builtin,
+        }
+    }
+
+    // Input in a container none of the tests convert to
+    const INPUT: &str = "/videos/clip.mov";
+
+    fn plan(container: &str, media: &MediaInfo, preset: &Preset) -> StreamCopy {
+        plan_stream_copy(
+            INPUT,
+            container,
+            media,
+            preset,
+            &ConversionOptions::default(),
+        )
+    }
+
+    #[test]
+    fn builtin_presets_copy_supported_streams() {
+        let copy = plan("mkv", &media("hevc", "opus"), &preset(true));
+
+        assert_eq!(
+            copy,
+            StreamCopy {
+                video: true,
+                audio: true
+            }
+        );
+    }
+
+    #[test]
+    fn only_streams_the_container_supports_are_copied() {
+        let copy = plan("webm", &media("vp9", "aac"), &preset(true));
+
+        assert!(copy.video);
+        assert!(!copy.audio);
+        assert!(!plan("gif", &media("h264", "aac"), &preset(true)).any());
+    }
+
+    #[test]
+    fn user_presets_copy_only_their_own_codecs() {
+        let copy = plan("mp4", &media("hevc", "aac"), &preset(false));
+
+        assert!(!copy.video);
+        assert!(copy.audio);
+        assert!(plan("mp4", &media("h264", "aac"), &preset(false)).video);
+    }
+
+    #[test]
+    fn transforms_need_a_reencode() {
+        let options = ConversionOptions {
+            crf: Some(23),
+            audio_bitrate: Some("192k".into()),
+            ..ConversionOptions::default()
+        };
+
+        assert!(
+            !plan_stream_copy(INPUT, "mp4", &media("h264", "aac"), &preset(true), &options).any()
+        );
+
+        let mut with_args = preset(true);
+        with_args.extra_args = vec!["-tune".into(), "film".into()];
+
+        assert!(!plan("mp4", &media("h264", "aac"), &with_args).any());
+    }
+
+    #[test]
+    fn same_container_is_reencoded_unless_remuxing() {
+        let media = media("h264", "aac");
+        let remux = ConversionOptions {
+            remux: true,
+            ..ConversionOptions::default()
+        };
+
+        let plan_from = |input: &str, options: &ConversionOptions| {
+            plan_stream_copy(input, "mp4", &media, &preset(true), options)
+        };
+
+        assert!(!plan_from("/videos/clip.MP4", &ConversionOptions::default()).any());
+        assert!(plan_from("/videos/clip.mp4", &remux).video);
+        assert!(plan_from("/videos/clip.mkv", &ConversionOptions::default()).video);
+    }
+
+    #[test]
+    fn unknown_streams_are_never_copied() {
+        assert!(!plan("mp4", &MediaInfo::default(), &preset(true)).any());
+    }
+}
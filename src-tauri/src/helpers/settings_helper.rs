@@ -1,92 +1,529 @@
-use std::{fs, path::PathBuf};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::{Duration, SystemTime},
+};
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tauri::{path::BaseDirectory, Manager};
+use serde_json::{json, Map, Value};
+use tauri::State;
 
-use crate::{helpers::queue_helper::PipelineManager, tools::app_handle::app};
+use crate::{
+    helpers::{
+        discovery_helper::{inspect_ffmpeg, FfmpegOrigin},
+        gpu_helper::{get_gpu_hw_encoder, get_vendor, GpuVendor},
+        log_helper::FfmpegLogLevel,
+        output_helper::{CollisionPolicy, OutputRule},
+        queue_helper::PipelineManager,
+    },
+    tools::{
+        engine::{data_path, emit, engine, SettingsSource},
+        error::Error,
+    },
+};
 
-#[derive(Debug, Serialize, Deserialize)]
+// Version of the settings file, bumped together with a migration whenever a field changes
+pub const SETTINGS_VERSION: u32 = 2;
+
+// More parallel FFmpeg processes than this only slow every job down
+const MAX_CONCURRENCY: usize = 16;
+
+// How often the settings file is checked for changes made outside the app
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ConversionMode {
+    #[default]
+    Normal,
+    // Uses the hardware encoder of the GPU when there is one for the codec
+    Hwaccel,
+    Lossless,
+}
+
+// CPU encoders the settings can choose from, hardware encoders are picked from these in gpu_helper
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum VideoEncoder {
+    #[serde(rename = "libx264")]
+    Libx264,
+    #[default]
+    #[serde(rename = "libx265")]
+    Libx265,
+    #[serde(rename = "libvpx-vp9")]
+    LibvpxVp9,
+    #[serde(rename = "libsvtav1")]
+    Libsvtav1,
+}
+
+impl VideoEncoder {
+    // Name of the FFmpeg encoder
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VideoEncoder::Libx264 => "libx264",
+            VideoEncoder::Libx265 => "libx265",
+            VideoEncoder::LibvpxVp9 => "libvpx-vp9",
+            VideoEncoder::Libsvtav1 => "libsvtav1",
+        }
+    }
+
+    fn display_name(&self) -> &'static str {
+        match self {
+            VideoEncoder::Libx264 => "H.264",
+            VideoEncoder::Libx265 => "H.265",
+            VideoEncoder::LibvpxVp9 => "VP9",
+            VideoEncoder::Libsvtav1 => "AV1",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(default)]
 pub struct Settings {
-    pub conversion_mode: String,
+    // Written by the app, older files are migrated when they are loaded
+    pub schema_version: u32,
+    pub conversion_mode: ConversionMode,
     pub max_concurrency: usize,
     pub open_when_finished: bool,
-    pub default_encoder: String,
+    pub default_encoder: VideoEncoder,
+    // Re-encode even if the streams could be copied into the new container as they are
+    pub force_reencode: bool,
+    // What happens when the output file already exists or another job writes to the same file
+    pub collision_policy: CollisionPolicy,
+    // Output folder and file name of jobs that don't bring their own rule
+    pub output: OutputRule,
+    // How detailed the FFmpeg output in the job logs is
+    pub ffmpeg_log_level: FfmpegLogLevel,
+    // FFmpeg chosen by the user, e.g. the one of the distribution. The downloaded one is used if unset.
+    pub ffmpeg_path: Option<String>,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Self {
-            conversion_mode: "normal".into(),
+            schema_version: SETTINGS_VERSION,
+            conversion_mode: ConversionMode::default(),
             max_concurrency: 1,
             open_when_finished: true,
-            default_encoder: "libx265".into(),
+            default_encoder: VideoEncoder::default(),
+            force_reencode: false,
+            collision_policy: CollisionPolicy::default(),
+            output: OutputRule::default(),
+            ffmpeg_log_level: FfmpegLogLevel::default(),
+            ffmpeg_path: None,
         }
     }
 }
 
-fn get_settings_path() -> PathBuf {
-    let settings_path = app()
-        .path()
-        .resolve("settings/default.json", BaseDirectory::AppLocalData)
-        .expect("error resolving settings file");
-
-    if let Some(parent) = settings_path.parent() {
-        if !parent.exists() || !settings_path.exists() {
-            let default = Settings::default();
-            let json = serde_json::to_string_pretty(&default).expect("error serializing settings");
-            fs::create_dir_all(parent).expect("error creating settings directory");
-            let settings_clone = settings_path.clone();
-            fs::write(settings_clone, json).expect("error creating settings file");
-            let settings_clone = settings_path.clone();
-
-            return settings_clone;
+impl Settings {
+    // Concurrency the queue can run with, a file edited by hand may hold any number
+    pub fn concurrency_limit(&self) -> usize {
+        self.max_concurrency.clamp(1, MAX_CONCURRENCY)
+    }
+
+    // Rejects settings a conversion can't run with, the message is shown as it is.
+    // `current` are the saved settings, the FFmpeg binary is only checked when it was changed, so a
+    // removed FFmpeg doesn't block saving unrelated settings.
+    pub async fn validate(&self, current: &Settings) -> Result<(), Error> {
+        if !(1..=MAX_CONCURRENCY).contains(&self.max_concurrency) {
+            return Err(Error::InvalidSettings(format!(
+                "Concurrent conversions must be between 1 and {}",
+                MAX_CONCURRENCY
+            )));
         }
+
+        if self.conversion_mode == ConversionMode::Hwaccel {
+            let vendor = if cfg!(target_os = "macos") {
+                GpuVendor::APPLE
+            } else {
+                get_vendor().await
+            };
+
+            // Without a known GPU every job runs on the CPU anyway, that's not worth an error
+            if vendor != GpuVendor::UNKNOWN
+                && get_gpu_hw_encoder(self.default_encoder.as_str(), vendor).is_none()
+            {
+                return Err(Error::InvalidSettings(format!(
+                    "Your GPU can't encode {} with hardware acceleration, choose another encoder or the Normal mode",
+                    self.default_encoder.display_name()
+                )));
+            }
+        }
+
+        // A binary that doesn't run would fail every job
+        if let Some(path) = self
+            .ffmpeg_path
+            .as_ref()
+            .filter(|path| current.ffmpeg_path.as_ref() != Some(path))
+        {
+            inspect_ffmpeg(Path::new(path), FfmpegOrigin::Custom)
+                .await
+                .map_err(|e| Error::InvalidSettings(e.to_string()))?;
+        }
+
+        self.output
+            .validate()
+            .map_err(|e| Error::InvalidSettings(e.to_string()))
+    }
+}
+
+// Migration from version 1, which stored the mode and encoder as free-form strings. Values that were
+// silently ignored back then are replaced by their defaults.
+fn migrate_v1(settings: &mut Map<String, Value>) {
+    let mode = settings
+        .get("conversion_mode")
+        .and_then(Value::as_str)
+        .map(|mode| mode.trim().to_lowercase());
+
+    let mode = match mode.as_deref() {
+        Some("hwaccel") => "hwaccel",
+        Some("lossless") => "lossless",
+        _ => "normal",
+    };
+    settings.insert("conversion_mode".into(), json!(mode));
+
+    let encoder_known = settings
+        .get("default_encoder")
+        .is_some_and(|encoder| serde_json::from_value::<VideoEncoder>(encoder.clone()).is_ok());
+
+    if !encoder_known {
+        settings.remove("default_encoder");
     }
 
-    settings_path
+    if settings.get("max_concurrency").and_then(Value::as_u64) == Some(0) {
+        settings.insert("max_concurrency".into(), json!(1));
+    }
 }
 
-#[tauri::command]
-pub fn reset_settings() {
-    let path = get_settings_path();
-    let default = Settings::default();
-    let json = serde_json::to_string_pretty(&default).expect("error serializing settings");
-    fs::write(path, json).expect("error writing settings file");
+// MIGRATIONS[n] takes a file of version n + 1 to version n + 2
+const MIGRATIONS: &[fn(&mut Map<String, Value>)] = &[migrate_v1];
+
+fn migrate(mut value: Value) -> Value {
+    let Some(settings) = value.as_object_mut() else {
+        return value;
+    };
+
+    // Files without a version are from before the versioning
+    let version = settings
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .unwrap_or(1)
+        .max(1) as usize;
+
+    for migration in MIGRATIONS.iter().skip(version - 1) {
+        migration(settings);
+    }
+
+    if version < SETTINGS_VERSION as usize {
+        settings.insert("schema_version".into(), json!(SETTINGS_VERSION));
+    }
+
+    value
 }
 
-#[tauri::command]
-pub fn load_settings() -> Settings {
-    let path = get_settings_path();
+fn get_settings_path() -> PathBuf {
+    data_path("settings/default.json").expect("error resolving settings file")
+}
+
+// The UI saves on every change while jobs read the file, a half written file must never be visible
+fn write_settings_file(path: &Path, settings: &Settings) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let json = serde_json::to_string_pretty(settings).map_err(|e| Error::Io(e.to_string()))?;
+
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, json)?;
+    fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+fn file_modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
 
-    let data = fs::read_to_string(&path).unwrap_or_else(|_| "{}".to_string());
+// Settings from a file that doesn't deserialize as a whole. Fields are taken one by one, a field with
+// an invalid value (e.g. an unknown conversion mode) keeps its default instead of resetting the rest.
+fn repair_settings(value: Value) -> Settings {
+    let Value::Object(fields) = value else {
+        return Settings::default();
+    };
 
-    let settings = serde_json::from_str(&data).unwrap_or_default();
+    let mut repaired = match serde_json::to_value(Settings::default()) {
+        Ok(Value::Object(defaults)) => defaults,
+        _ => return Settings::default(),
+    };
+
+    for (key, field) in fields {
+        let mut candidate = repaired.clone();
+        candidate.insert(key.clone(), field);
+
+        match serde_json::from_value::<Settings>(Value::Object(candidate.clone())) {
+            Ok(_) => repaired = candidate,
+            Err(e) => log::warn!("invalid setting {}, using the default: {}", key, e),
+        }
+    }
+
+    serde_json::from_value(Value::Object(repaired)).unwrap_or_default()
+}
+
+// Reads the settings file, migrating it and filling in missing fields
+fn read_settings_file(path: &Path) -> Settings {
+    let data = fs::read_to_string(path).unwrap_or_else(|_| "{}".to_string());
+
+    // The file is kept as a backup before anything in it is replaced below
+    let back_up = || {
+        fs::copy(path, path.with_extension("json.bak")).ok();
+    };
+
+    let settings = match serde_json::from_str::<Value>(&data) {
+        Ok(value) => {
+            let value = migrate(value);
+
+            serde_json::from_value(value.clone()).unwrap_or_else(|e| {
+                log::error!(
+                    "error reading settings, replacing the invalid values: {}",
+                    e
+                );
+                back_up();
+                repair_settings(value)
+            })
+        }
+        Err(e) => {
+            log::error!("error reading settings, using the defaults: {}", e);
+            back_up();
+            Settings::default()
+        }
+    };
 
     let new_json = serde_json::to_string_pretty(&settings).expect("error serializing settings");
 
     // have to rewrite the settings file if it was missing fields
     if data.trim() != new_json.trim() {
-        fs::write(&path, new_json).expect("error writing settings file");
+        if let Err(e) = write_settings_file(path, &settings) {
+            log::error!("error writing settings file: {}", e);
+        }
     }
 
     settings
 }
 
+struct CachedSettings {
+    settings: Settings,
+    // Modification time of the file when it was read, a different one means it was edited outside the app
+    modified: Option<SystemTime>,
+}
+
+// Settings stored as JSON in the data folder, used by both the app and the command line.
+// The file is read once and kept in memory, a job takes a copy when it starts.
+#[derive(Default)]
+pub struct SettingsFile {
+    cache: RwLock<Option<CachedSettings>>,
+}
+
+impl SettingsSource for SettingsFile {
+    fn load(&self) -> Settings {
+        if let Some(cached) = self.cache.read().unwrap().as_ref() {
+            return cached.settings.clone();
+        }
+
+        let mut cache = self.cache.write().unwrap();
+
+        // Another thread may have read the file while this one waited for the lock
+        if let Some(cached) = cache.as_ref() {
+            return cached.settings.clone();
+        }
+
+        let path = get_settings_path();
+        let settings = read_settings_file(&path);
+
+        *cache = Some(CachedSettings {
+            settings: settings.clone(),
+            modified: file_modified(&path),
+        });
+
+        settings
+    }
+
+    fn save(&self, settings: &Settings) -> Result<(), Error> {
+        let path = get_settings_path();
+
+        {
+            let mut cache = self.cache.write().unwrap();
+            write_settings_file(&path, settings)?;
+
+            *cache = Some(CachedSettings {
+                settings: settings.clone(),
+                modified: file_modified(&path),
+            });
+        }
+
+        emit("settings-changed", settings);
+
+        Ok(())
+    }
+
+    fn reload_if_changed(&self) -> Option<Settings> {
+        let path = get_settings_path();
+
+        let settings = {
+            let mut cache = self.cache.write().unwrap();
+
+            // Nothing was read yet, the next load reads the file anyway
+            let cached = cache.as_mut()?;
+            let modified = file_modified(&path);
+
+            if modified.is_none() || cached.modified == modified {
+                return None;
+            }
+
+            cached.settings = read_settings_file(&path);
+            cached.modified = file_modified(&path);
+            cached.settings.clone()
+        };
+
+        log::info!("settings file changed, reloaded it");
+        emit("settings-changed", &settings);
+
+        Some(settings)
+    }
+}
+
+// Picks up edits of the settings file made outside the app
+pub fn start_settings_watcher(manager: PipelineManager) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(RELOAD_INTERVAL).await;
+
+            if let Some(settings) = engine().settings().reload_if_changed() {
+                manager.set_concurrency(settings.concurrency_limit()).await;
+            }
+        }
+    });
+}
+
 #[tauri::command]
-pub async fn save_settings(new_settings: Settings) {
-    let path = get_settings_path();
+pub async fn reset_settings(manager: State<'_, PipelineManager>) -> Result<(), Error> {
+    let settings = Settings::default();
+
+    engine().settings().save(&settings)?;
+    manager.set_concurrency(settings.max_concurrency).await;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub fn load_settings() -> Settings {
+    engine().settings().load()
+}
+
+#[tauri::command]
+pub async fn save_settings(
+    mut new_settings: Settings,
+    manager: State<'_, PipelineManager>,
+) -> Result<(), Error> {
     let current_settings = load_settings();
 
+    new_settings.validate(&current_settings).await?;
+    new_settings.schema_version = SETTINGS_VERSION;
+
+    // Saved first, so the queue never runs with a limit that isn't on disk
+    engine().settings().save(&new_settings)?;
+
     // Need to update pipeline manager if concurrency changed
     if new_settings.max_concurrency != current_settings.max_concurrency {
-        let pipeline_manager: tauri::State<PipelineManager> = app().state();
-        pipeline_manager
-            .set_concurrency(new_settings.max_concurrency)
-            .await;
+        manager.set_concurrency(new_settings.max_concurrency).await;
+    }
+
+    Ok(())
+}
+
+// JSON schema of the settings, the UI builds its inputs and checks values with it
+#[tauri::command]
+pub fn get_settings_schema() -> Value {
+    serde_json::to_value(schemars::schema_for!(Settings)).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn migrated(value: Value) -> Settings {
+        serde_json::from_value(migrate(value)).unwrap()
     }
 
-    let json = serde_json::to_string_pretty(&new_settings).expect("error serializing settings");
+    #[test]
+    fn migrates_unversioned_files() {
+        let settings = migrated(json!({
+            "conversion_mode": " HWAccel ",
+            "max_concurrency": 0,
+            "default_encoder": "h264_nvenc",
+            "open_when_finished": false,
+        }));
 
-    fs::write(path, json).expect("error writing settings file");
+        assert_eq!(settings.schema_version, SETTINGS_VERSION);
+        assert_eq!(settings.conversion_mode, ConversionMode::Hwaccel);
+        assert_eq!(settings.max_concurrency, 1);
+        assert_eq!(settings.default_encoder, VideoEncoder::default());
+        assert!(!settings.open_when_finished);
+    }
+
+    #[test]
+    fn unknown_mode_falls_back_to_normal() {
+        let settings = migrated(json!({ "schema_version": 1, "conversion_mode": "turbo" }));
+
+        assert_eq!(settings.conversion_mode, ConversionMode::Normal);
+    }
+
+    #[test]
+    fn keeps_known_encoder() {
+        let settings = migrated(json!({ "default_encoder": "libsvtav1" }));
+
+        assert_eq!(settings.default_encoder, VideoEncoder::Libsvtav1);
+    }
+
+    #[test]
+    fn invalid_values_fall_back_one_by_one() {
+        let settings = repair_settings(json!({
+            "schema_version": SETTINGS_VERSION,
+            "conversion_mode": "turbo",
+            "collision_policy": 7,
+            "max_concurrency": 4,
+            "open_when_finished": false,
+        }));
+
+        assert_eq!(settings.conversion_mode, ConversionMode::Normal);
+        assert_eq!(settings.collision_policy, CollisionPolicy::default());
+        assert_eq!(settings.max_concurrency, 4);
+        assert!(!settings.open_when_finished);
+    }
+
+    #[test]
+    fn current_files_are_not_changed() {
+        let value = json!({
+            "schema_version": SETTINGS_VERSION,
+            "conversion_mode": "lossless",
+            "max_concurrency": 4,
+        });
+
+        assert_eq!(migrate(value.clone()), value);
+    }
+
+    #[test]
+    fn concurrency_limit_is_clamped() {
+        let limit = |max_concurrency| {
+            Settings {
+                max_concurrency,
+                ..Settings::default()
+            }
+            .concurrency_limit()
+        };
+
+        assert_eq!(limit(0), 1);
+        assert_eq!(limit(4), 4);
+        assert_eq!(limit(1000), MAX_CONCURRENCY);
+    }
 }
@@ -0,0 +1,95 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    helpers::output_helper::remove_temp_output,
+    tools::{engine::data_path, error::Error, job_control::process_alive},
+};
+
+// Temp outputs that are being written, with the process writing them. The app and the command line
+// can run at the same time, so a sweep only removes the files of processes that are gone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TempEntry {
+    path: String,
+    pid: u32,
+}
+
+// Serializes the read-modify-write cycles on the registry file
+static TEMP_LOCK: Mutex<()> = Mutex::new(());
+
+fn get_registry_path() -> Result<PathBuf, Error> {
+    data_path("temp/outputs.json")
+}
+
+fn load_entries() -> Vec<TempEntry> {
+    get_registry_path()
+        .ok()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+fn save_entries(entries: &[TempEntry]) -> Result<(), Error> {
+    let path = get_registry_path()?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let json = serde_json::to_string(entries).map_err(|e| Error::Io(e.to_string()))?;
+
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, json)?;
+    fs::rename(&tmp_path, &path)?;
+
+    Ok(())
+}
+
+fn update_entries(update: impl FnOnce(&mut Vec<TempEntry>)) {
+    let _lock = TEMP_LOCK.lock().unwrap();
+    let mut entries = load_entries();
+
+    update(&mut entries);
+
+    if let Err(e) = save_entries(&entries) {
+        log::error!("error saving the temp file registry: {}", e);
+    }
+}
+
+// Called before a job writes its temp output, so it can be removed if the process dies
+pub fn register_temp_output(temp_path: &Path) {
+    let entry = TempEntry {
+        path: temp_path.to_string_lossy().to_string(),
+        pid: std::process::id(),
+    };
+
+    update_entries(|entries| entries.push(entry));
+}
+
+// Called once the temp output was renamed to the output or removed
+pub fn unregister_temp_output(temp_path: &Path) {
+    let path = temp_path.to_string_lossy();
+
+    update_entries(|entries| entries.retain(|entry| entry.path != path));
+}
+
+// Removes the temp outputs left by crashed or killed sessions of the app and the command line
+pub fn sweep_temp_outputs() {
+    update_entries(|entries| {
+        entries.retain(|entry| {
+            let orphaned = entry.pid == std::process::id() || !process_alive(entry.pid);
+
+            if orphaned {
+                log::info!("removing temp file of a previous session: {}", entry.path);
+                remove_temp_output(Path::new(&entry.path));
+            }
+
+            !orphaned
+        })
+    });
+}
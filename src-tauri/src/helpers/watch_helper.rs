@@ -0,0 +1,354 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    helpers::{
+        ffmpeg_helper::ConversionOptions,
+        file_helper::{file_category, scan_folder, FolderFilter},
+        output_helper::{CollisionPolicy, OutputRule},
+        preset_helper::find_preset,
+        queue_helper::{JobPriority, JobRequest, PipelineManager},
+    },
+    tools::{
+        engine::{data_path, emit},
+        error::Error,
+    },
+};
+
+// How often the watched folders are scanned
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// A folder that converts every new file in it with a preset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchFolder {
+    #[serde(default)]
+    pub id: String,
+    pub path: String,
+    pub preset: String,
+    #[serde(default)]
+    pub output: OutputRule,
+    #[serde(default)]
+    pub filter: FolderFilter,
+    #[serde(default)]
+    pub options: ConversionOptions,
+    #[serde(default)]
+    pub collision: Option<CollisionPolicy>,
+    #[serde(default = "enabled_default")]
+    pub enabled: bool,
+    // By default only files added after the folder started being watched are converted
+    #[serde(default)]
+    pub convert_existing: bool,
+}
+
+fn enabled_default() -> bool {
+    true
+}
+
+// A file is identified by its size and modification time too, so a replaced file is converted again
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ProcessedFile {
+    path: String,
+    size: u64,
+    modified: u64,
+}
+
+// Serializes the read-modify-write cycles on the watch files
+static WATCH_LOCK: Mutex<()> = Mutex::new(());
+
+fn get_watch_path(file: &str) -> PathBuf {
+    data_path(&format!("watch/{}", file)).expect("error resolving watch folder file")
+}
+
+fn read_json<T: for<'de> Deserialize<'de> + Default>(file: &str) -> T {
+    fs::read_to_string(get_watch_path(file))
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+fn write_json<T: Serialize>(file: &str, value: &T) -> Result<(), Error> {
+    let path = get_watch_path(file);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let json = serde_json::to_string_pretty(value).map_err(|e| Error::Io(e.to_string()))?;
+
+    // The processed list is written while conversions are running, a crash mid-write shouldn't lose it
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, json)?;
+    fs::rename(&tmp_path, &path)?;
+
+    Ok(())
+}
+
+fn load_folders() -> Vec<WatchFolder> {
+    read_json("folders.json")
+}
+
+fn load_processed() -> HashMap<String, Vec<ProcessedFile>> {
+    read_json("processed.json")
+}
+
+fn processed_file(path: &Path) -> Option<ProcessedFile> {
+    let metadata = fs::metadata(path).ok()?;
+
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+
+    Some(ProcessedFile {
+        path: path.to_string_lossy().to_string(),
+        size: metadata.len(),
+        modified,
+    })
+}
+
+// Files of the folder that would be converted, files already in the target format are left alone
+// so outputs written into the watched folder are not converted again
+fn candidate_files(folder: &WatchFolder, container: &str) -> Result<Vec<PathBuf>, Error> {
+    let mut filter = folder.filter.clone();
+    filter.limit_to_target(container);
+
+    let files = scan_folder(Path::new(&folder.path), &filter)?
+        .into_iter()
+        .filter(|file| {
+            !file
+                .extension()
+                .and_then(|s| s.to_str())
+                .is_some_and(|e| e.eq_ignore_ascii_case(container))
+        })
+        .collect();
+
+    Ok(files)
+}
+
+impl WatchFolder {
+    fn validate(&self) -> Result<(), Error> {
+        if !Path::new(&self.path).is_dir() {
+            return Err(Error::NotAFolder(self.path.clone()));
+        }
+
+        if find_preset(&self.preset).is_none() {
+            return Err(Error::PresetNotFound(self.preset.clone()));
+        }
+
+        self.output.validate()
+    }
+
+    // Marks the files that are already in the folder as processed
+    fn record_existing_files(&self, processed: &mut HashMap<String, Vec<ProcessedFile>>) {
+        let Some(preset) = find_preset(&self.preset) else {
+            return;
+        };
+
+        let files = candidate_files(self, &preset.container).unwrap_or_default();
+
+        processed.insert(
+            self.id.clone(),
+            files
+                .iter()
+                .filter_map(|file| processed_file(file))
+                .collect(),
+        );
+    }
+}
+
+#[tauri::command]
+pub fn list_watch_folders() -> Vec<WatchFolder> {
+    load_folders()
+}
+
+#[tauri::command]
+pub fn add_watch_folder(mut folder: WatchFolder) -> Result<WatchFolder, Error> {
+    folder.validate()?;
+    folder.id = Uuid::new_v4().to_string();
+
+    let _lock = WATCH_LOCK.lock().unwrap();
+    let mut folders = load_folders();
+
+    if !folder.convert_existing {
+        let mut processed = load_processed();
+        folder.record_existing_files(&mut processed);
+        write_json("processed.json", &processed)?;
+    }
+
+    folders.push(folder.clone());
+    write_json("folders.json", &folders)?;
+
+    Ok(folder)
+}
+
+#[tauri::command]
+pub fn update_watch_folder(folder: WatchFolder) -> Result<(), Error> {
+    folder.validate()?;
+
+    let _lock = WATCH_LOCK.lock().unwrap();
+    let mut folders = load_folders();
+
+    let position = folders
+        .iter()
+        .position(|f| f.id == folder.id)
+        .ok_or_else(|| Error::WatchFolderNotFound(folder.id.clone()))?;
+
+    folders[position] = folder;
+
+    write_json("folders.json", &folders)
+}
+
+#[tauri::command]
+pub fn remove_watch_folder(id: String) -> Result<(), Error> {
+    let _lock = WATCH_LOCK.lock().unwrap();
+    let mut folders = load_folders();
+
+    let position = folders
+        .iter()
+        .position(|f| f.id == id)
+        .ok_or_else(|| Error::WatchFolderNotFound(id.clone()))?;
+
+    folders.remove(position);
+
+    let mut processed = load_processed();
+    processed.remove(&id);
+
+    write_json("processed.json", &processed)?;
+    write_json("folders.json", &folders)
+}
+
+// Forgets which files were converted, so every file in the folder is converted again
+#[tauri::command]
+pub fn clear_watch_history(id: String) -> Result<(), Error> {
+    let _lock = WATCH_LOCK.lock().unwrap();
+    let mut processed = load_processed();
+
+    processed.remove(&id);
+
+    write_json("processed.json", &processed)
+}
+
+// Size and modification time of a file the last time it was seen, a file is only converted once
+// it didn't change between two scans, so files that are still being copied are left alone
+type PendingFiles = HashMap<PathBuf, (u64, u64)>;
+
+async fn poll_folder(
+    folder: &WatchFolder,
+    pending: &mut PendingFiles,
+    manager: &PipelineManager,
+) -> Result<(), Error> {
+    let preset =
+        find_preset(&folder.preset).ok_or_else(|| Error::PresetNotFound(folder.preset.clone()))?;
+    let files = candidate_files(folder, &preset.container)?;
+
+    let processed = {
+        let _lock = WATCH_LOCK.lock().unwrap();
+        load_processed().remove(&folder.id).unwrap_or_default()
+    };
+
+    let mut ready = Vec::new();
+
+    // Files that were deleted or moved away before they settled are forgotten
+    pending.retain(|path, _| files.contains(path));
+
+    for file in files {
+        let Some(current) = processed_file(&file) else {
+            continue;
+        };
+
+        if processed.contains(&current) || current.size == 0 {
+            continue;
+        }
+
+        match pending.insert(file.clone(), (current.size, current.modified)) {
+            Some(previous) if previous == (current.size, current.modified) => {
+                pending.remove(&file);
+                ready.push(current);
+            }
+            _ => (),
+        }
+    }
+
+    if ready.is_empty() {
+        return Ok(());
+    }
+
+    for file in &ready {
+        let extension = Path::new(&file.path)
+            .extension()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+
+        let job = JobRequest {
+            id: Uuid::new_v4().to_string(),
+            path: file.path.clone(),
+            extension: preset.container.clone(),
+            category: file_category(extension).unwrap_or("video").to_string(),
+            priority: JobPriority::default(),
+            preset: Some(folder.preset.clone()),
+            options: folder.options.clone(),
+            collision: folder.collision,
+            output: Some(folder.output.clone()),
+        };
+
+        match manager.add_job(job).await {
+            Ok(job_id) => {
+                emit(
+                    "watch-job-queued",
+                    json!({ "folder_id": folder.id, "path": file.path, "job_id": job_id }),
+                );
+            }
+            Err(e) => log::error!("error queueing {} from watch folder: {}", file.path, e),
+        }
+    }
+
+    // Files are recorded even if they couldn't be queued, a broken file shouldn't be retried forever
+    {
+        let _lock = WATCH_LOCK.lock().unwrap();
+        let mut processed = load_processed();
+
+        processed
+            .entry(folder.id.clone())
+            .or_default()
+            .extend(ready);
+        write_json("processed.json", &processed)?;
+    }
+
+    manager.try_dispatch();
+
+    Ok(())
+}
+
+// Scans the enabled watch folders until the app is closed
+pub fn start_watching(manager: PipelineManager) {
+    tauri::async_runtime::spawn(async move {
+        let mut pending: HashMap<String, PendingFiles> = HashMap::new();
+
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+
+            let folders = load_folders();
+
+            // Dropping the state of folders that were removed or disabled
+            pending.retain(|id, _| folders.iter().any(|f| f.enabled && f.id == *id));
+
+            for folder in folders.iter().filter(|f| f.enabled) {
+                let folder_pending = pending.entry(folder.id.clone()).or_default();
+
+                if let Err(e) = poll_folder(folder, folder_pending, &manager).await {
+                    log::error!("error scanning watch folder {}: {}", folder.path, e);
+                }
+            }
+        }
+    });
+}
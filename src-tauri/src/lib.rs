@@ -1,28 +1,61 @@
-use serde::Serialize;
-use std::{env, fs::metadata, path::Path};
+use std::{env, fs::metadata, sync::Arc};
 use tauri::{Manager, State};
-use uuid::Uuid;
+use tauri_plugin_log::{RotationStrategy, Target, TargetKind};
 
+mod cli;
 mod helpers;
 mod tools;
 
+#[cfg(test)]
+mod tests;
+
+pub use cli::is_cli_command;
+pub use tools::engine::take_portable_flag;
+
 use helpers::{
+    discovery_helper::{find_ffmpeg, get_ffmpeg_info, select_ffmpeg},
+    file_helper::{enqueue_folder, expand_folder, file_details, forwarded_files, FileDetails},
+    history_helper::{clear_history, export_history, query_history, rerun_history_entry},
+    log_helper::{get_job_log, log_dir, open_log_folder},
     net_helper::check_connection,
+    output_helper::preview_output_path,
+    preset_helper::{
+        create_preset, delete_preset, export_presets, import_presets, list_presets, update_preset,
+    },
+    probe_helper::probe_media,
     queue_helper::PipelineManager,
-    settings_helper::{load_settings, reset_settings, save_settings},
+    settings_helper::{
+        get_settings_schema, load_settings, reset_settings, save_settings, start_settings_watcher,
+        SettingsFile,
+    },
+    temp_helper::sweep_temp_outputs,
+    watch_helper::{
+        add_watch_folder, clear_watch_history, list_watch_folders, remove_watch_folder,
+        start_watching, update_watch_folder,
+    },
+};
+use tools::{
+    app_handle::{TauriEvents, TauriPaths},
+    engine::{
+        data_path, emit, init_engine, Engine, LocalDataDir, PathProvider, PortableDir,
+        PORTABLE_FLAG,
+    },
+    installer::{install_ffmpeg, is_ffmpeg_installed},
 };
-use tools::installer::{install_ffmpeg, is_ffmpeg_installed};
 
-use crate::{helpers::queue_helper::JobRequest, tools::error::Error};
+use crate::{
+    helpers::{
+        ffmpeg_helper::ConversionOptions,
+        journal_helper::JournalEntry,
+        output_helper::{CollisionPolicy, OutputRule},
+        queue_helper::{CollisionAnswer, JobPriority, JobRequest},
+    },
+    tools::error::Error,
+};
 
-#[derive(Debug, Serialize)]
-struct FileDetails {
-    id: String,
-    full_file_name: String,
-    file_extension: String,
-    file_name: String,
-    dir_path: String,
-}
+// The app log is rotated at 5 MB, the last few files are kept
+const APP_LOG_SIZE: u128 = 5_000_000;
+const APP_LOG_FILES: usize = 5;
 
 #[tauri::command]
 fn check_type(path: &str) -> bool {
@@ -32,54 +65,28 @@ fn check_type(path: &str) -> bool {
 
 #[tauri::command]
 fn get_file_data(path: &str) -> Result<FileDetails, Error> {
-    let file_path = Path::new(path);
-
-    if !file_path.is_file() {
-        return Err(Error::NotAFile(path.to_string()));
-    }
-
-    let full_file_name = file_path
-        .file_name()
-        .and_then(|s| s.to_str())
-        .ok_or(Error::InvalidPath)?
-        .to_string();
-    let file_extension = file_path
-        .extension()
-        .and_then(|s| s.to_str())
-        .ok_or(Error::InvalidPath)?
-        .to_string();
-    let file_name = file_path
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .ok_or(Error::InvalidPath)?
-        .to_string();
-    let dir_path = file_path
-        .parent()
-        .and_then(|s| s.to_str())
-        .ok_or(Error::InvalidPath)?
-        .to_string();
-
-    Ok(FileDetails {
-        id: Uuid::new_v4().to_string(),
-        full_file_name,
-        file_extension,
-        file_name,
-        dir_path,
-    })
+    file_details(path)
 }
 
 #[tauri::command]
 fn get_args() -> Vec<String> {
-    let args: Vec<String> = std::env::args().collect();
+    let args: Vec<String> = std::env::args()
+        .filter(|arg| arg != PORTABLE_FLAG)
+        .collect();
     args
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn add_job(
     id: String,
     path: String,
     extension: String,
     category: String,
+    preset: Option<String>,
+    options: Option<ConversionOptions>,
+    collision: Option<CollisionPolicy>,
+    output: Option<OutputRule>,
     manager: State<'_, PipelineManager>,
 ) -> Result<String, String> {
     manager
@@ -88,17 +95,27 @@ async fn add_job(
             path,
             extension,
             category,
+            priority: JobPriority::default(),
+            preset,
+            options: options.unwrap_or_default(),
+            collision,
+            output,
         })
         .await
         .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn convert(
     id: String,
     path: String,
     extension: String,
     category: String,
+    preset: Option<String>,
+    options: Option<ConversionOptions>,
+    collision: Option<CollisionPolicy>,
+    output: Option<OutputRule>,
     manager: State<'_, PipelineManager>,
 ) -> Result<(), String> {
     let _ = manager
@@ -107,6 +124,11 @@ async fn convert(
             path,
             extension,
             category,
+            priority: JobPriority::default(),
+            preset,
+            options: options.unwrap_or_default(),
+            collision,
+            output,
         })
         .await
         .map_err(|e| e.to_string())?;
@@ -121,9 +143,16 @@ async fn add_all_jobs(
     files: Vec<JobRequest>,
     manager: State<'_, PipelineManager>,
 ) -> Result<(), Error> {
-    manager.add_jobs(files).await;
+    manager.add_jobs(files).await
+}
 
-    Ok(())
+#[tauri::command]
+async fn resolve_collision(
+    id: String,
+    answer: CollisionAnswer,
+    manager: State<'_, PipelineManager>,
+) -> Result<(), Error> {
+    manager.resolve_collision(id, answer).await
 }
 
 #[tauri::command]
@@ -137,11 +166,93 @@ async fn cancel_all_jobs(manager: State<'_, PipelineManager>) -> Result<(), Erro
     Ok(())
 }
 
+#[tauri::command]
+async fn pause_job(id: String, manager: State<'_, PipelineManager>) -> Result<(), Error> {
+    manager.pause_job(id).await
+}
+
+#[tauri::command]
+async fn resume_job(id: String, manager: State<'_, PipelineManager>) -> Result<(), Error> {
+    manager.resume_job(id).await
+}
+
+#[tauri::command]
+async fn pause_all_jobs(manager: State<'_, PipelineManager>) -> Result<(), Error> {
+    manager.pause_all().await;
+    Ok(())
+}
+
+#[tauri::command]
+async fn resume_all_jobs(manager: State<'_, PipelineManager>) -> Result<(), Error> {
+    manager.resume_all().await;
+    Ok(())
+}
+
+#[tauri::command]
+async fn move_job(
+    id: String,
+    position: usize,
+    manager: State<'_, PipelineManager>,
+) -> Result<(), Error> {
+    manager.move_job(id, position).await
+}
+
+#[tauri::command]
+async fn move_job_to_front(id: String, manager: State<'_, PipelineManager>) -> Result<(), Error> {
+    manager.move_job_to_front(id).await
+}
+
+#[tauri::command]
+async fn move_job_to_back(id: String, manager: State<'_, PipelineManager>) -> Result<(), Error> {
+    manager.move_job_to_back(id).await
+}
+
+#[tauri::command]
+async fn set_job_priority(
+    id: String,
+    priority: JobPriority,
+    manager: State<'_, PipelineManager>,
+) -> Result<(), Error> {
+    manager.set_job_priority(id, priority).await
+}
+
+#[tauri::command]
+async fn get_queue_order(manager: State<'_, PipelineManager>) -> Result<Vec<String>, Error> {
+    Ok(manager.queue_order().await)
+}
+
+#[tauri::command]
+fn get_queue_journal(manager: State<'_, PipelineManager>) -> Vec<JournalEntry> {
+    manager.journal()
+}
+
+#[tauri::command]
+fn clear_queue_journal(manager: State<'_, PipelineManager>) {
+    manager.clear_journal();
+}
+
+#[tauri::command]
+fn start_queue(manager: State<'_, PipelineManager>) {
+    manager.try_dispatch();
+}
+
+#[tauri::command]
+async fn requeue_job(id: String, manager: State<'_, PipelineManager>) -> Result<(), Error> {
+    manager.requeue_job(id).await
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
+pub fn run(portable: bool) {
     tauri::Builder::default()
         .plugin(tauri_plugin_updater::Builder::new().build())
-        .plugin(tauri_plugin_single_instance::init(|app, _argv, _cwd| {
+        .plugin(tauri_plugin_single_instance::init(|app, argv, cwd| {
+            // Files opened while the app is running are added to the running instance
+            let opened = forwarded_files(&argv, &cwd);
+
+            if !opened.is_empty() {
+                emit("files-opened", opened);
+            }
+
             let window = app.get_webview_window("main").unwrap();
             window.show().unwrap();
             window.set_focus().unwrap();
@@ -152,31 +263,135 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .setup(move |app| {
             let handle = app.handle().clone();
-            tools::app_handle::init_global_app(handle);
+            let portable_dir = PortableDir::detect(portable);
+            let is_portable = portable_dir.is_some();
+
+            let paths: Arc<dyn PathProvider> = match portable_dir {
+                Some(dir) => Arc::new(dir),
+                None => Arc::new(TauriPaths(handle.clone())),
+            };
+
+            init_engine(Engine::new(
+                Arc::new(TauriEvents(handle)),
+                paths,
+                Arc::new(SettingsFile::default()),
+            ));
+
+            // The app log lives next to the job logs, so open_log_folder shows both
+            app.handle().plugin(
+                tauri_plugin_log::Builder::new()
+                    .clear_targets()
+                    .target(Target::new(TargetKind::Stdout))
+                    .target(Target::new(TargetKind::Folder {
+                        path: log_dir()?,
+                        file_name: Some("draconv".into()),
+                    }))
+                    .level(log::LevelFilter::Info)
+                    .max_file_size(APP_LOG_SIZE)
+                    .rotation_strategy(RotationStrategy::KeepSome(APP_LOG_FILES))
+                    .build(),
+            )?;
+
+            sweep_temp_outputs();
+
+            if is_portable {
+                log::info!("Portable mode, data folder: {}", data_path("")?.display());
+            }
 
             let settings = load_settings();
 
-            let manager = PipelineManager::new(settings.max_concurrency);
-            app.manage(manager);
+            let manager = PipelineManager::new(settings.concurrency_limit());
+            app.manage(manager.clone());
+            manager.resume_restored_jobs();
+
+            start_settings_watcher(manager.clone());
+            start_watching(manager);
 
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             get_file_data,
+            expand_folder,
+            enqueue_folder,
             check_type,
             get_args,
             load_settings,
             save_settings,
             reset_settings,
+            get_settings_schema,
             install_ffmpeg,
             is_ffmpeg_installed,
             check_connection,
+            list_presets,
+            create_preset,
+            update_preset,
+            delete_preset,
+            import_presets,
+            export_presets,
+            probe_media,
+            preview_output_path,
+            list_watch_folders,
+            add_watch_folder,
+            update_watch_folder,
+            remove_watch_folder,
+            clear_watch_history,
+            query_history,
+            export_history,
+            rerun_history_entry,
+            clear_history,
+            get_job_log,
+            open_log_folder,
+            find_ffmpeg,
+            get_ffmpeg_info,
+            select_ffmpeg,
             add_job,
             convert,
             add_all_jobs,
+            resolve_collision,
             cancel_job,
             cancel_all_jobs,
+            pause_job,
+            resume_job,
+            pause_all_jobs,
+            resume_all_jobs,
+            move_job,
+            move_job_to_front,
+            move_job_to_back,
+            set_job_priority,
+            get_queue_order,
+            get_queue_journal,
+            clear_queue_journal,
+            requeue_job,
+            start_queue,
         ])
-        .run(tauri::generate_context!())
+        .run(context())
         .expect("error while running tauri application");
 }
+
+// Runs a command line command without a window and returns the exit code of the process
+pub fn run_cli(args: Vec<String>, portable: bool) -> i32 {
+    cli::attach_console();
+
+    // The command line doesn't start Tauri at all, it reads the same data folder as the app
+    let paths: Arc<dyn PathProvider> = match PortableDir::detect(portable) {
+        Some(dir) => Arc::new(dir),
+        None => Arc::new(LocalDataDir::new(context().config().identifier.as_str())),
+    };
+
+    init_engine(
+        Engine::new(
+            Arc::new(cli::ConsoleEvents),
+            paths,
+            Arc::new(SettingsFile::default()),
+        )
+        .headless(),
+    );
+
+    sweep_temp_outputs();
+
+    tauri::async_runtime::block_on(cli::run(args))
+}
+
+fn context() -> tauri::Context {
+    tauri::generate_context!()
+}
@@ -2,5 +2,12 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 fn main() {
-    app_lib::run();
+    let mut args: Vec<String> = std::env::args().collect();
+    let portable = app_lib::take_portable_flag(&mut args);
+
+    if app_lib::is_cli_command(&args) {
+        std::process::exit(app_lib::run_cli(args, portable));
+    }
+
+    app_lib::run(portable);
 }
@@ -0,0 +1,121 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+
+use crate::{
+    helpers::settings_helper::SettingsFile,
+    tools::{
+        engine::{init_engine, Engine, EventSink, PathProvider},
+        error::Error,
+    },
+};
+
+mod pipeline;
+
+// How long a test waits for an event before it fails
+const EVENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Keeps every event, so tests can check what the frontend would have been told
+#[derive(Default)]
+pub struct RecordedEvents {
+    events: Mutex<Vec<(String, Value)>>,
+}
+
+impl EventSink for RecordedEvents {
+    fn emit(&self, event: &str, payload: Value) {
+        self.events
+            .lock()
+            .unwrap()
+            .push((event.to_string(), payload));
+    }
+}
+
+impl RecordedEvents {
+    pub fn clear(&self) {
+        self.events.lock().unwrap().clear();
+    }
+
+    // Names of the events about a single job, in the order they were emitted
+    pub fn of_job(&self, id: &str) -> Vec<String> {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, payload)| payload["id"] == id)
+            .map(|(event, _)| event.clone())
+            .collect()
+    }
+
+    fn find(&self, event: &str, id: Option<&str>) -> Option<Value> {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(name, payload)| name == event && id.map_or(true, |id| payload["id"] == id))
+            .map(|(_, payload)| payload.clone())
+    }
+
+    // Payload of the first `event`, of the job `id` if one is given. Jobs run on the async runtime
+    // of Tauri, so the test polls until they get there.
+    pub async fn wait_for(&self, event: &str, id: Option<&str>) -> Value {
+        let started = Instant::now();
+
+        loop {
+            if let Some(payload) = self.find(event, id) {
+                return payload;
+            }
+
+            if started.elapsed() > EVENT_TIMEOUT {
+                panic!("no {} event for {:?}", event, id);
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+struct TempDataDir(PathBuf);
+
+impl PathProvider for TempDataDir {
+    fn data_dir(&self) -> Result<PathBuf, Error> {
+        Ok(self.0.clone())
+    }
+}
+
+// Empty folder for a single test, below a folder of this test run in the temp directory
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join(format!("draconv-test-{}", std::process::id()))
+        .join(name);
+
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
+
+// The engine can only be set once per process, so every test shares this one. It keeps its files
+// in the temp directory and never opens finished files.
+pub fn test_engine() -> &'static RecordedEvents {
+    static EVENTS: OnceLock<Arc<RecordedEvents>> = OnceLock::new();
+
+    EVENTS.get_or_init(|| {
+        let events = Arc::new(RecordedEvents::default());
+
+        init_engine(
+            Engine::new(
+                events.clone(),
+                Arc::new(TempDataDir(temp_dir("data"))),
+                Arc::new(SettingsFile::default()),
+            )
+            .headless(),
+        );
+
+        events
+    })
+}
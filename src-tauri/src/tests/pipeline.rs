@@ -0,0 +1,292 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use tokio::sync::{Mutex, MutexGuard};
+use uuid::Uuid;
+
+use crate::{
+    helpers::{
+        ffmpeg_helper::ConversionOptions,
+        journal_helper::JobStatus,
+        output_helper::CollisionPolicy,
+        queue_helper::{CollisionAnswer, JobPriority, JobRequest, PipelineManager},
+    },
+    tests::{temp_dir, test_engine, RecordedEvents},
+    tools::{engine::data_path, error::Error},
+};
+
+// Images are converted without FFmpeg, so these tests run the whole pipeline on any machine
+struct Pipeline {
+    manager: PipelineManager,
+    events: &'static RecordedEvents,
+    dir: PathBuf,
+    _guard: MutexGuard<'static, ()>,
+}
+
+// The tests share the data folder of the engine, so they run one at a time and each one starts
+// with an empty journal and no events
+async fn pipeline(name: &str) -> Pipeline {
+    static LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+
+    let events = test_engine();
+    let guard = LOCK.get_or_init(|| Mutex::new(())).lock().await;
+
+    fs::remove_file(data_path("queue/journal.json").unwrap()).ok();
+    events.clear();
+
+    Pipeline {
+        manager: PipelineManager::new(1),
+        events,
+        dir: temp_dir(name),
+        _guard: guard,
+    }
+}
+
+impl Pipeline {
+    fn image(&self, name: &str) -> PathBuf {
+        let path = self.dir.join(name);
+
+        image::RgbImage::from_pixel(4, 4, image::Rgb([200, 40, 40]))
+            .save(&path)
+            .unwrap();
+
+        path
+    }
+
+    fn request(&self, path: &Path, category: &str, priority: JobPriority) -> JobRequest {
+        JobRequest {
+            id: Uuid::new_v4().to_string(),
+            path: path.to_string_lossy().to_string(),
+            extension: "bmp".into(),
+            category: category.into(),
+            priority,
+            preset: None,
+            options: ConversionOptions::default(),
+            collision: None,
+            output: None,
+        }
+    }
+
+    async fn add(&self, path: &Path, category: &str, priority: JobPriority) -> String {
+        let request = self.request(path, category, priority);
+
+        self.manager.add_job(request).await.unwrap()
+    }
+
+    async fn output_path(&self, id: &str) -> String {
+        let queued = self.events.wait_for("job-queued", Some(id)).await;
+
+        queued["output_path"].as_str().unwrap().to_string()
+    }
+
+    fn status(&self, id: &str) -> Option<JobStatus> {
+        self.manager
+            .journal()
+            .into_iter()
+            .find(|entry| entry.id == id)
+            .map(|entry| entry.status)
+    }
+}
+
+#[tokio::test]
+async fn queued_job_is_converted() {
+    let pipeline = pipeline("convert").await;
+    let input = pipeline.image("red.png");
+
+    let id = pipeline.add(&input, "image", JobPriority::Normal).await;
+    pipeline.manager.try_dispatch();
+
+    pipeline.events.wait_for("job-completed", Some(&id)).await;
+    pipeline.events.wait_for("all-jobs-completed", None).await;
+
+    assert_eq!(
+        pipeline.events.of_job(&id),
+        ["job-queued", "job-started", "job-completed"]
+    );
+    assert_eq!(pipeline.status(&id), Some(JobStatus::Completed));
+
+    let output = pipeline.dir.join("red.bmp");
+    assert_eq!(image::image_dimensions(&output).unwrap(), (4, 4));
+
+    // The temp file was renamed to the output, nothing else is left next to the input
+    assert_eq!(fs::read_dir(&pipeline.dir).unwrap().count(), 2);
+}
+
+#[tokio::test]
+async fn failed_job_is_reported() {
+    let pipeline = pipeline("fail").await;
+    let input = pipeline.image("red.png");
+
+    let id = pipeline.add(&input, "archive", JobPriority::Normal).await;
+    pipeline.manager.try_dispatch();
+
+    let failed = pipeline.events.wait_for("job-failed", Some(&id)).await;
+    pipeline.events.wait_for("all-jobs-completed", None).await;
+
+    assert!(failed["error"].as_str().unwrap().contains("archive"));
+    assert_eq!(
+        pipeline.events.of_job(&id),
+        ["job-queued", "job-started", "job-failed"]
+    );
+    assert_eq!(pipeline.status(&id), Some(JobStatus::Failed));
+    assert!(!pipeline.dir.join("red.bmp").exists());
+}
+
+#[tokio::test]
+async fn queue_is_ordered_by_priority() {
+    let pipeline = pipeline("order").await;
+    let input = pipeline.image("red.png");
+
+    let low = pipeline.add(&input, "image", JobPriority::Low).await;
+    let normal = pipeline.add(&input, "image", JobPriority::Normal).await;
+    let high = pipeline.add(&input, "image", JobPriority::High).await;
+
+    assert_eq!(
+        pipeline.manager.queue_order().await,
+        [high.as_str(), normal.as_str(), low.as_str()]
+    );
+
+    // A job moved in front of a high priority job becomes one itself
+    pipeline.manager.move_job(low.clone(), 0).await.unwrap();
+
+    assert_eq!(
+        pipeline.manager.queue_order().await,
+        [low.as_str(), high.as_str(), normal.as_str()]
+    );
+
+    let order = pipeline.events.wait_for("queue-order", None).await;
+    assert_eq!(order[0]["id"], low.as_str());
+    assert_eq!(order[0]["priority"], "high");
+
+    pipeline.manager.cancel_job(normal.clone()).await.unwrap();
+    assert_eq!(
+        pipeline.events.of_job(&normal),
+        ["job-queued", "job-cancelled"]
+    );
+    assert_eq!(pipeline.status(&normal), Some(JobStatus::Cancelled));
+
+    pipeline.manager.cancel_all().await;
+    assert!(pipeline.manager.queue_order().await.is_empty());
+    assert_eq!(pipeline.events.of_job(&low), ["job-queued"]);
+}
+
+#[tokio::test]
+async fn colliding_outputs_get_a_suffix() {
+    let pipeline = pipeline("collision").await;
+    let input = pipeline.image("red.png");
+
+    fs::write(pipeline.dir.join("red.bmp"), "existing").unwrap();
+
+    let first = pipeline.add(&input, "image", JobPriority::Normal).await;
+    let second = pipeline.add(&input, "image", JobPriority::Normal).await;
+
+    let output = |name: &str| pipeline.dir.join(name).to_string_lossy().to_string();
+
+    assert_eq!(pipeline.output_path(&first).await, output("red (1).bmp"));
+    assert_eq!(pipeline.output_path(&second).await, output("red (2).bmp"));
+
+    pipeline.manager.cancel_all().await;
+}
+
+#[tokio::test]
+async fn restored_jobs_are_started() {
+    let pipeline = pipeline("restore").await;
+    let input = pipeline.image("red.png");
+
+    let id = pipeline.add(&input, "image", JobPriority::Normal).await;
+
+    // A new manager reads the job from the journal, like the app after a restart
+    let restored = PipelineManager::new(1);
+    assert_eq!(restored.queue_order().await, [id.as_str()]);
+
+    restored.resume_restored_jobs();
+
+    pipeline.events.wait_for("job-completed", Some(&id)).await;
+    pipeline.events.wait_for("all-jobs-completed", None).await;
+
+    assert!(pipeline.dir.join("red.bmp").exists());
+}
+
+#[tokio::test]
+async fn unreadable_image_fails_the_job() {
+    let pipeline = pipeline("unreadable").await;
+    let input = pipeline.dir.join("broken.png");
+
+    fs::write(&input, "not an image").unwrap();
+
+    let id = pipeline.add(&input, "image", JobPriority::Normal).await;
+    pipeline.manager.try_dispatch();
+
+    let failed = pipeline.events.wait_for("job-failed", Some(&id)).await;
+    pipeline.events.wait_for("all-jobs-completed", None).await;
+
+    assert!(failed["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("Image processing error"));
+    assert_eq!(pipeline.status(&id), Some(JobStatus::Failed));
+}
+
+#[tokio::test]
+async fn taken_outputs_wait_for_an_answer() {
+    let pipeline = pipeline("ask").await;
+    let input = pipeline.image("red.png");
+
+    fs::write(pipeline.dir.join("red.bmp"), "existing").unwrap();
+
+    let mut ids = Vec::new();
+
+    for _ in 0..2 {
+        let request = JobRequest {
+            collision: Some(CollisionPolicy::Ask),
+            ..pipeline.request(&input, "image", JobPriority::Normal)
+        };
+
+        ids.push(pipeline.manager.add_job(request).await.unwrap());
+    }
+
+    for id in &ids {
+        assert_eq!(pipeline.events.of_job(id), ["job-collision"]);
+    }
+    assert!(pipeline.manager.queue_order().await.is_empty());
+
+    pipeline
+        .manager
+        .resolve_collision(ids[0].clone(), CollisionAnswer::Skip)
+        .await
+        .unwrap();
+    assert_eq!(
+        pipeline.events.of_job(&ids[0]),
+        ["job-collision", "job-skipped"]
+    );
+
+    pipeline
+        .manager
+        .resolve_collision(ids[1].clone(), CollisionAnswer::Rename)
+        .await
+        .unwrap();
+    pipeline
+        .events
+        .wait_for("job-completed", Some(&ids[1]))
+        .await;
+    pipeline.events.wait_for("all-jobs-completed", None).await;
+
+    let renamed = pipeline.dir.join("red (1).bmp");
+    assert_eq!(
+        pipeline.output_path(&ids[1]).await,
+        renamed.to_string_lossy()
+    );
+    assert!(renamed.exists());
+
+    // Every prompt is answered once
+    assert!(matches!(
+        pipeline
+            .manager
+            .resolve_collision(ids[1].clone(), CollisionAnswer::Overwrite)
+            .await,
+        Err(Error::JobNotFound)
+    ));
+}
@@ -1,17 +1,27 @@
-use std::sync::OnceLock;
-use tauri::AppHandle;
+use tauri::{AppHandle, Emitter, Manager};
 
-// Have to use a global app handle to avoid lifetime issues with Tauri's AppHandle
-// such as passing `app: AppHandle` to every function that needs it.
+use crate::tools::{
+    engine::{EventSink, PathProvider},
+    error::Error,
+};
 
-static GLOBAL_APP: OnceLock<AppHandle> = OnceLock::new();
+// Engine backends for the Tauri app: events go to the frontend and files live in AppLocalData
 
-pub fn init_global_app(app: AppHandle) {
-    GLOBAL_APP
-        .set(app)
-        .expect("AppHandle is already initialized");
+pub struct TauriEvents(pub AppHandle);
+
+impl EventSink for TauriEvents {
+    fn emit(&self, event: &str, payload: serde_json::Value) {
+        let _ = self.0.emit(event, payload);
+    }
 }
 
-pub fn app() -> &'static AppHandle {
-    GLOBAL_APP.get().expect("AppHandle is not initialized")
+pub struct TauriPaths(pub AppHandle);
+
+impl PathProvider for TauriPaths {
+    fn data_dir(&self) -> Result<std::path::PathBuf, Error> {
+        self.0
+            .path()
+            .app_local_data_dir()
+            .map_err(|e| Error::Io(e.to_string()))
+    }
 }
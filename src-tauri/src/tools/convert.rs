@@ -1,189 +1,489 @@
 use image::imageops::FilterType;
 use image::GenericImageView;
 use serde_json::json;
+use std::collections::VecDeque;
 use std::fs;
 use std::process::Stdio;
-use std::{path::Path, sync::Arc};
-use tauri::{path::BaseDirectory, Emitter, Manager};
+use std::{path::Path, sync::Arc, time::Duration};
 use tauri_plugin_opener::open_path;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::Command;
-use tokio::sync::Notify;
 use uuid::Uuid;
 
 use crate::helpers::{
-    ffmpeg_helper::{ffmpeg_builder, get_video_duration_millis, time_to_seconds},
+    failure_helper::classify_failure,
+    ffmpeg_helper::{ffmpeg_builder, get_ffmpeg_path, hw_failure_reason, FfmpegArgs},
+    gpu_helper::mark_hw_encoder_failed,
+    log_helper::{ffmpeg_errors, JobLog},
+    output_helper::{commit_output, remove_temp_output, temp_output_path},
+    preset_helper::resolve_preset,
+    probe_helper::{probe, MediaInfo},
+    progress_helper::ProgressTracker,
+    queue_helper::PipelineJob,
     settings_helper,
+    temp_helper::{register_temp_output, unregister_temp_output},
 };
 
-use crate::tools::{app_handle::app, error::Error, stopwatch};
+use crate::tools::{
+    engine::{emit, engine},
+    error::Error,
+    job_control::{self, JobControl},
+    stopwatch::{self, Stopwatch},
+};
+
+// Stderr lines kept in memory for the error message, the full output is in the job log
+const STDERR_TAIL: usize = 200;
+
+// What a conversion actually ran. It is filled in while the job runs, so failed jobs can be
+// recorded in the history too.
+#[derive(Debug, Default)]
+pub struct ConversionReport {
+    // FFmpeg arguments of the last attempt, with the final output path instead of the temp file
+    pub arguments: Vec<String>,
+    pub video_encoder: Option<String>,
+    pub audio_encoder: Option<String>,
+    // Duration of the input in seconds
+    pub duration: Option<f64>,
+    pub encode_time: Duration,
+}
 
 pub async fn exec_conversion(
-    id: Uuid,
-    path: &str,
-    extension: &str,
-    category: &str,
-    cancel_notify: &Arc<Notify>,
+    job: &PipelineJob,
+    control: &Arc<JobControl>,
+    report: &mut ConversionReport,
 ) -> Result<(), Error> {
-    let file = Path::new(&path);
-    let stem = file
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .ok_or(Error::InvalidPath)?;
-    let full_path = file.parent().unwrap().join(stem);
-    let output_path = format!("{}.{}", full_path.to_str().unwrap().to_owned(), &extension);
-
-    let args = ffmpeg_builder(&path, &output_path, &extension).await;
+    let log = Arc::new(JobLog::create(&job.id));
+
+    log.line(&format!("Job {} ({})", job.id, job.category));
+    log.line(&format!("Input: {}", job.path));
+    log.line(&format!("Output: {}", job.output_path));
+    log.line(&format!(
+        "Format: {}, preset: {}",
+        job.extension,
+        job.preset.as_deref().unwrap_or("none")
+    ));
+    log.line(&format!("Options: {:?}", job.options));
+
+    log::info!(
+        "job {} started: {} -> {}",
+        job.id,
+        job.path,
+        job.output_path
+    );
+
+    // Registered for the whole job, so a crash leaves nothing behind that the next start doesn't remove
+    let temp_path = temp_output_path(&job.output_path, job.id);
+    register_temp_output(&temp_path);
+
+    let result = convert_job(job, control, report, &log).await;
+
+    // By now the temp file was either renamed to the output or is of no use
+    remove_temp_output(&temp_path);
+    unregister_temp_output(&temp_path);
+
+    match &result {
+        Ok(_) => {
+            log.line(&format!(
+                "Completed in {:.2}s",
+                report.encode_time.as_secs_f64()
+            ));
+            log::info!("job {} completed", job.id);
+        }
+        Err(Error::ConversionCancelled) => {
+            log.line("Cancelled");
+            log::info!("job {} cancelled", job.id);
+        }
+        Err(e) => {
+            log.line(&format!("Failed: {}", e));
+            log::error!("job {} failed: {}", job.id, e);
+        }
+    }
+
+    result
+}
+
+async fn convert_job(
+    job: &PipelineJob,
+    control: &Arc<JobControl>,
+    report: &mut ConversionReport,
+    log: &Arc<JobLog>,
+) -> Result<(), Error> {
+    let id = job.id;
+    let path = job.path.as_str();
+    let extension = job.extension.as_str();
+    let output_path = job.output_path.as_str();
+    let options = &job.options;
+
+    let temp_path = temp_output_path(output_path, id);
+    let temp_str = temp_path.to_string_lossy().to_string();
+
+    // Output folders from an output rule are only created once a job actually starts
+    if let Some(parent) = temp_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let file = Path::new(path);
+
+    // Snapshot for the whole job, the retries use the same settings as the first run
     let settings = settings_helper::load_settings();
 
-    let _ = app().emit("job-started", true);
+    emit("job-started", true);
 
-    match category {
+    match job.category.as_str() {
         "video" | "audio" => {
-            let ffmpeg_path = app()
-                .path()
-                .resolve(
-                    if cfg!(target_os = "windows") {
-                        "bin/ffmpeg.exe"
-                    } else {
-                        "bin/ffmpeg"
-                    },
-                    BaseDirectory::AppLocalData,
-                )
-                .map_err(|e| Error::Io(e.to_string()))?;
+            let ffmpeg_path = get_ffmpeg_path()?;
+
+            let media = probe(path).await?;
+            report.duration = media.duration;
+
+            log.line(&format!(
+                "Media: {}, {} streams, duration {}",
+                media.container.as_deref().unwrap_or("unknown format"),
+                media.streams.len(),
+                media
+                    .duration
+                    .map_or("unknown".to_string(), |d| format!("{:.2}s", d))
+            ));
+
+            let preset = resolve_preset(job.preset.as_deref(), extension);
 
             let mut timer = stopwatch::Stopwatch::new();
 
+            let mut allow_hw = true;
+            let mut allow_copy = true;
+
+            // A job is retried with a safer command when the first attempt fails for a reason that
+            // re-encoding on the CPU can solve. Every retry turns one option off, so this ends after three runs.
+            let (result, built) = loop {
+                // Every attempt is timed on its own, the encode time of the job is the one of the last run
+                timer.reset();
+                timer.start();
+
+                let built = ffmpeg_builder(
+                    path,
+                    &temp_str,
+                    preset.clone(),
+                    options,
+                    &media,
+                    allow_hw,
+                    allow_copy,
+                    &settings,
+                )
+                .await;
+
+                log.line(&format!(
+                    "Hardware encoder: {}, copied streams: video {}, audio {}",
+                    built.hw_encoder.as_deref().unwrap_or("none"),
+                    built.stream_copy.video,
+                    built.stream_copy.audio
+                ));
+
+                let result = run_ffmpeg(
+                    id,
+                    &ffmpeg_path,
+                    &built,
+                    &temp_path,
+                    &media,
+                    control,
+                    &mut timer,
+                    log,
+                )
+                .await;
+
+                let stderr = match &result {
+                    Err(Error::FfmpegFailed(failure)) => &failure.message,
+                    _ => break (result, built),
+                };
+
+                // If the hardware encoder or decoder could not be initialized, the job is retried on the CPU
+                // and the encoder is skipped for the rest of the session
+                let hw_failure = built
+                    .hw_encoder
+                    .as_ref()
+                    .and_then(|encoder| Some((encoder, hw_failure_reason(stderr)?)));
+
+                let reason = if let Some((encoder, reason)) = hw_failure {
+                    mark_hw_encoder_failed(encoder);
+                    allow_hw = false;
+                    reason
+                } else if built.stream_copy.any() {
+                    allow_copy = false;
+                    "The streams could not be copied into the new container"
+                } else {
+                    break (result, built);
+                };
+
+                log.line(&format!("Retrying: {}", reason));
+
+                emit(
+                    "job-retried",
+                    json!({
+                        "id": id.to_string(),
+                        "encoder": built.hw_encoder,
+                        "reason": reason,
+                    }),
+                );
+            };
+
+            report.arguments = built
+                .args
+                .iter()
+                .map(|arg| {
+                    if *arg == temp_str {
+                        output_path.to_string()
+                    } else {
+                        arg.clone()
+                    }
+                })
+                .collect();
+            report.video_encoder = built.codec("-c:v");
+            report.audio_encoder = built.codec("-c:a");
+            report.encode_time = timer.elapsed();
+
+            result?;
+
+            verify_media_output(&temp_path).await?;
+            commit_output(&temp_path, output_path)?;
+
+            let total_time = timer.elapsed().as_secs();
+            let data = json!({
+                "id": id.to_string(),
+                "total_time": total_time,
+                "input_file": path,
+                "new_file_path": output_path,
+                "remuxed": built.stream_copy.any(),
+                "copied_video": built.stream_copy.video,
+                "copied_audio": built.stream_copy.audio,
+            });
+
+            emit("job-completed", data);
+
+            timer.reset();
+
+            if settings.open_when_finished && !engine().is_headless() {
+                open_path(output_path, None::<&str>).map_err(|e| Error::Io(e.to_string()))?;
+            }
+        }
+
+        "image" => {
+            //if cancel_notify.notified().await { return Err(Error::ConversionCancelled); }
+
+            let mut timer = Stopwatch::new();
             timer.start();
 
-            let mut command = Command::new(&ffmpeg_path);
+            // Any file can reach this from the command line, an unreadable one fails the job
+            let mut img = image::open(file).map_err(|e| Error::ImageError(e.to_string()))?;
 
-            command
-                .args(&args)
-                .stdin(Stdio::null())
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped());
+            // Resizing keeps the aspect ratio, a missing side doesn't limit the size
+            if options.width.is_some() || options.height.is_some() {
+                img = img.resize(
+                    options.width.unwrap_or(u32::MAX),
+                    options.height.unwrap_or(u32::MAX),
+                    FilterType::Lanczos3,
+                );
+            }
 
-            #[cfg(target_os = "windows")]
-            #[allow(unused_imports)]
-            {
-                use std::os::windows::process::CommandExt;
-                command.creation_flags(0x08000000);
+            if extension == "ico" {
+                let (width, height) = img.dimensions();
+                let max_size = 256;
+                let scale = (max_size as f32 / width.max(height) as f32).min(1.0);
+
+                let new_width = (width as f32 * scale).round() as u32;
+                let new_height = (height as f32 * scale).round() as u32;
+
+                img = img.resize(new_width, new_height, FilterType::Lanczos3);
             }
 
-            let mut child = command.spawn()?;
+            log.line(&format!("Saving {}x{} image", img.width(), img.height()));
 
-            let stdout = child.stdout.take().ok_or(Error::FfmpegStdout)?;
-            let mut reader = BufReader::new(stdout).lines();
+            let saved = img.save(&temp_path);
+            report.encode_time = timer.elapsed();
 
-            let total_duration_sec = get_video_duration_millis(ffmpeg_path, &path);
+            if let Err(e) = saved {
+                remove_temp_output(&temp_path);
+                return Err(Error::ImageError(e.to_string()));
+            }
 
-            let loop_result = loop {
-                tokio::select! {
-                    line = reader.next_line() => {
-                        match line {
-                            Ok(Some(line_str)) => {
-                                let parts: Vec<&str> = line_str.split("=").collect();
+            // Reading the header back is enough to know the encoder finished the file
+            if let Err(e) = image::image_dimensions(&temp_path) {
+                remove_temp_output(&temp_path);
+                return Err(Error::OutputVerification(e.to_string()));
+            }
 
-                                if parts.len() == 2 && parts[0] == "out_time" {
-                                    let current_sec = time_to_seconds(parts[1].trim());
-                                    let percentage = ((current_sec / total_duration_sec) * 100.0).min(100.0) as i32;
+            commit_output(&temp_path, output_path)?;
+            emit("job-completed", json!({ "id": id }));
 
-                                    let _ = app().emit("job-progress", json!({ "id": id.to_string(), "progress": percentage }));
-                                }
-                            },
+            if settings.open_when_finished && !engine().is_headless() {
+                open_path(output_path, None::<&str>)
+                    .map_err(|e| Error::ImageError(e.to_string()))?;
+            }
+        }
 
-                            Ok(None) => break Ok(()),
-                            Err(e) => break Err(Error::Io(e.to_string()))
-                        }
-                    },
+        _ => {
+            return Err(Error::InvalidCategory(job.category.clone()));
+        }
+    }
 
-                    _ = cancel_notify.notified() => {
-                        child.kill().await.map_err(|e| Error::Io(e.to_string()))?;
-                        let _ = child.wait().await;
+    Ok(())
+}
 
-                        let file = Path::new(&output_path);
+// FFmpeg can exit successfully and still leave a file without any readable stream, e.g. when
+// every stream was filtered out. Such a file is removed instead of being moved to the output path.
+async fn verify_media_output(temp_path: &Path) -> Result<(), Error> {
+    let result = match probe(&temp_path.to_string_lossy()).await {
+        Ok(media) if !media.streams_known() && media.duration.is_none() => Err(
+            Error::OutputVerification("The output has no streams".into()),
+        ),
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::OutputVerification(e.to_string())),
+    };
+
+    if result.is_err() {
+        remove_temp_output(temp_path);
+    }
 
-                        if file.exists() {
-                            fs::remove_file(file).ok();
-                        }
+    result
+}
 
-                        break Err(Error::ConversionCancelled);
-                    }
-                }
-            };
+// Runs a single FFmpeg process until it exits, reporting progress and reacting to pause and cancel requests.
+// Stderr is read while FFmpeg runs and written to the job log. On failure the partial output is removed
+// and the errors FFmpeg logged are returned in FfmpegFailed.
+#[allow(clippy::too_many_arguments)]
+async fn run_ffmpeg(
+    id: Uuid,
+    ffmpeg_path: &Path,
+    built: &FfmpegArgs,
+    output_path: &Path,
+    media: &MediaInfo,
+    control: &Arc<JobControl>,
+    timer: &mut Stopwatch,
+    log: &Arc<JobLog>,
+) -> Result<(), Error> {
+    log.command(ffmpeg_path, &built.args);
+
+    let mut command = Command::new(ffmpeg_path);
+
+    command
+        .args(&built.args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    #[cfg(target_os = "windows")]
+    #[allow(unused_imports)]
+    {
+        use std::os::windows::process::CommandExt;
+        command.creation_flags(0x08000000);
+    }
+
+    let mut child = command.spawn()?;
 
-            if let Err(e) = loop_result {
-                return Err(e);
+    let stdout = child.stdout.take().ok_or(Error::FfmpegStdout)?;
+    let mut reader = BufReader::new(stdout).lines();
+
+    // Reading stderr only after FFmpeg exits would block it once the pipe is full at verbose log levels
+    let stderr = child.stderr.take().ok_or(Error::FfmpegStdout)?;
+    let stderr_log = log.clone();
+
+    let stderr_task = tauri::async_runtime::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        let mut tail = VecDeque::new();
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            stderr_log.line(&line);
+
+            if tail.len() == STDERR_TAIL {
+                tail.pop_front();
             }
+            tail.push_back(line);
+        }
 
-            let output = child.wait_with_output().await?;
+        tail
+    });
 
-            if output.status.success() {
-                let total_time = timer.elapsed().as_secs();
-                let data = json!({
-                    "id": id.to_string(),
-                    "total_time": total_time,
-                    "input_file": path,
-                    "new_file_path": output_path
-                });
+    let mut tracker = ProgressTracker::new(id.to_string(), media);
 
-                let _ = app().emit("job-completed", data);
+    // Whether the FFmpeg process is currently suspended
+    let mut suspended = false;
 
-                timer.reset();
+    let loop_result = loop {
+        tokio::select! {
+            line = reader.next_line() => {
+                match line {
+                    Ok(Some(line_str)) => {
+                        if let Some(progress) = tracker.feed(&line_str, timer.elapsed()) {
+                            emit("job-progress", progress);
+                        }
+                    },
 
-                if settings.open_when_finished {
-                    open_path(output_path, None::<&str>).unwrap();
+                    Ok(None) => break Ok(()),
+                    Err(e) => break Err(Error::Io(e.to_string()))
                 }
-            } else {
-                let error_message = String::from_utf8_lossy(&output.stderr).to_string();
-                let file = Path::new(&output_path);
+            },
 
-                if file.exists() {
-                    fs::remove_file(file).ok();
+            _ = control.state_changed() => {
+                let paused = control.is_paused();
+
+                if paused == suspended {
+                    continue;
                 }
 
-                let error_line = error_message
-                    .split("\n")
-                    .find(|line| line.contains("Encoder not found"))
-                    .is_some();
+                let Some(pid) = child.id() else {
+                    continue;
+                };
 
-                if error_line {
-                    return Err(Error::FfmpegFailed("Your GPU does not support hardware acceleration for this encoder. Please try to change the encoder or use CPU (Normal) encoding.".to_string()));
+                let result = if paused {
+                    job_control::suspend_process(pid)
                 } else {
-                    return Err(Error::FfmpegFailed(error_message));
+                    job_control::resume_process(pid)
+                };
+
+                match result {
+                    Ok(_) => {
+                        suspended = paused;
+
+                        if paused {
+                            timer.stop();
+                            emit("job-paused", json!({ "id": id.to_string() }));
+                        } else {
+                            timer.start();
+                            emit("job-resumed", json!({ "id": id.to_string() }));
+                        }
+                    }
+                    Err(e) => {
+                        log::error!("could not change the pause state of job {}: {}", id, e);
+                        control.set_paused(suspended);
+                    }
                 }
-            }
-        }
+            },
 
-        "image" => {
-            //if cancel_notify.notified().await { return Err(Error::ConversionCancelled); }
+            _ = control.cancelled() => {
+                child.kill().await.map_err(|e| Error::Io(e.to_string()))?;
+                let _ = child.wait().await;
 
-            let mut img = image::open(file).unwrap();
+                remove_temp_output(output_path);
 
-            if extension == "ico" {
-                let (width, height) = img.dimensions();
-                let max_size = 256;
-                let scale = (max_size as f32 / width.max(height) as f32).min(1.0);
+                break Err(Error::ConversionCancelled);
+            }
+        }
+    };
 
-                let new_width = (width as f32 * scale).round() as u32;
-                let new_height = (height as f32 * scale).round() as u32;
+    if let Err(e) = loop_result {
+        return Err(e);
+    }
 
-                img = img.resize(new_width, new_height, FilterType::Lanczos3);
-            }
+    let status = child.wait().await?;
+    let stderr = stderr_task.await.unwrap_or_default();
 
-            img.save(&output_path)
-                .map_err(|e| Error::ImageError(e.to_string()))?;
-            let _ = app().emit("job-completed", json!({ "id": id }));
+    log.line(&format!("FFmpeg exited with {}", status));
 
-            if settings.open_when_finished {
-                open_path(&output_path, None::<&str>).unwrap();
-            }
-        }
+    if !status.success() {
+        remove_temp_output(output_path);
 
-        _ => {
-            return Err(Error::InvalidCategory(category.to_string()));
-        }
+        return Err(Error::FfmpegFailed(classify_failure(
+            ffmpeg_errors(&stderr),
+            built.hw_encoder.is_some(),
+        )));
     }
 
     Ok(())
@@ -0,0 +1,167 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{helpers::settings_helper::Settings, tools::error::Error};
+
+// The queue, the converters and the installer only talk to the outside through these three traits,
+// so they can be driven by the Tauri app, the command line or a test without a running window.
+
+// Receives every event the engine emits, the app forwards them to the frontend
+pub trait EventSink: Send + Sync {
+    fn emit(&self, event: &str, payload: Value);
+}
+
+// Folder the app keeps its files in (settings, presets, queue journal, FFmpeg binaries...)
+pub trait PathProvider: Send + Sync {
+    fn data_dir(&self) -> Result<PathBuf, Error>;
+}
+
+pub trait SettingsSource: Send + Sync {
+    fn load(&self) -> Settings;
+    fn save(&self, settings: &Settings) -> Result<(), Error>;
+
+    // Picks up changes made outside the engine, returns the new settings if there were any
+    fn reload_if_changed(&self) -> Option<Settings> {
+        None
+    }
+}
+
+pub struct Engine {
+    events: Arc<dyn EventSink>,
+    paths: Arc<dyn PathProvider>,
+    settings: Arc<dyn SettingsSource>,
+    // Nobody is in front of the screen, so finished files are not opened
+    headless: bool,
+}
+
+impl Engine {
+    pub fn new(
+        events: Arc<dyn EventSink>,
+        paths: Arc<dyn PathProvider>,
+        settings: Arc<dyn SettingsSource>,
+    ) -> Self {
+        Self {
+            events,
+            paths,
+            settings,
+            headless: false,
+        }
+    }
+
+    pub fn headless(mut self) -> Self {
+        self.headless = true;
+        self
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.headless
+    }
+
+    pub fn settings(&self) -> &dyn SettingsSource {
+        self.settings.as_ref()
+    }
+}
+
+// Same reasoning as the old global AppHandle: the engine is needed nearly everywhere and passing it
+// to every function would touch every signature. It is set once when the process starts.
+static ENGINE: OnceLock<Engine> = OnceLock::new();
+
+pub fn init_engine(engine: Engine) {
+    if ENGINE.set(engine).is_err() {
+        panic!("Engine is already initialized");
+    }
+}
+
+pub fn engine() -> &'static Engine {
+    ENGINE.get().expect("Engine is not initialized")
+}
+
+pub fn emit<S: Serialize>(event: &str, payload: S) {
+    match serde_json::to_value(payload) {
+        Ok(payload) => engine().events.emit(event, payload),
+        Err(e) => log::error!("error serializing {} event: {}", event, e),
+    }
+}
+
+// Path inside the data folder, e.g. "settings/default.json"
+pub fn data_path(relative: &str) -> Result<PathBuf, Error> {
+    Ok(engine().paths.data_dir()?.join(relative))
+}
+
+// The folder Tauri uses for AppLocalData, for running without Tauri (e.g. from the command line)
+pub struct LocalDataDir {
+    identifier: String,
+}
+
+impl LocalDataDir {
+    pub fn new(identifier: &str) -> Self {
+        Self {
+            identifier: identifier.to_string(),
+        }
+    }
+}
+
+impl PathProvider for LocalDataDir {
+    fn data_dir(&self) -> Result<PathBuf, Error> {
+        let env_dir = |name: &str| std::env::var_os(name).map(PathBuf::from);
+
+        let base = if cfg!(target_os = "windows") {
+            env_dir("LOCALAPPDATA")
+        } else if cfg!(target_os = "macos") {
+            env_dir("HOME").map(|home| home.join("Library/Application Support"))
+        } else {
+            env_dir("XDG_DATA_HOME")
+                .filter(|dir| dir.is_absolute())
+                .or_else(|| env_dir("HOME").map(|home| home.join(".local/share")))
+        };
+
+        base.map(|base| base.join(&self.identifier))
+            .ok_or_else(|| Error::Io("Could not find the local data folder".into()))
+    }
+}
+
+// Turns on portable mode for a single run
+pub const PORTABLE_FLAG: &str = "--portable";
+
+// A file with this name next to the executable turns on portable mode for good
+const PORTABLE_MARKER: &str = "draconv.portable";
+
+// Removes `--portable` from the arguments, so the rest parse as usual. Returns whether it was there.
+pub fn take_portable_flag(args: &mut Vec<String>) -> bool {
+    let count = args.len();
+    args.retain(|arg| arg != PORTABLE_FLAG);
+
+    args.len() != count
+}
+
+// Data folder next to the executable, for running from a USB stick or a machine where AppLocalData
+// is not writable or not kept between sessions
+pub struct PortableDir {
+    root: PathBuf,
+}
+
+impl PortableDir {
+    // Portable mode is on when the flag was given or the marker file is next to the executable
+    pub fn detect(flag: bool) -> Option<Self> {
+        let exe_dir = std::env::current_exe().ok()?.parent()?.to_path_buf();
+
+        if !flag && !exe_dir.join(PORTABLE_MARKER).is_file() {
+            return None;
+        }
+
+        Some(Self {
+            root: exe_dir.join("data"),
+        })
+    }
+}
+
+impl PathProvider for PortableDir {
+    fn data_dir(&self) -> Result<PathBuf, Error> {
+        Ok(self.root.clone())
+    }
+}
@@ -1,18 +1,26 @@
 use serde::Serialize;
 use thiserror::Error;
 
+use crate::helpers::failure_helper::FfmpegFailure;
+
 #[derive(Debug, Serialize, Error)]
 pub enum Error {
     #[error("Path is not a file: {0}")]
     NotAFile(String),
+    #[error("Path is not a folder: {0}")]
+    NotAFolder(String),
     #[error("Invalid file path")]
     InvalidPath,
     #[error("I/O error: {0}")]
     Io(String),
     #[error("Conversion was cancelled by the user")]
     ConversionCancelled,
-    #[error("FFmpeg process failed: {0}")]
-    FfmpegFailed(String),
+    #[error("{0}")]
+    FfmpegFailed(FfmpegFailure),
+    #[error("FFmpeg is not installed, open Draconv once to install it")]
+    FfmpegNotInstalled,
+    #[error("Not a working FFmpeg: {0}")]
+    InvalidFfmpeg(String),
     #[error("Could not capture FFmpeg stdout")]
     FfmpegStdout,
     #[error("Image processing error: {0}")]
@@ -21,6 +29,55 @@ pub enum Error {
     InvalidCategory(String),
     #[error("Job not found")]
     JobNotFound,
+    #[error("Preset not found: {0}")]
+    PresetNotFound(String),
+    #[error("A preset with this name already exists: {0}")]
+    PresetExists(String),
+    #[error("Invalid preset: {0}")]
+    InvalidPreset(String),
+    #[error("Could not read media information: {0}")]
+    ProbeFailed(String),
+    #[error("Output file already exists: {0}")]
+    OutputExists(String),
+    #[error("The converted file could not be verified: {0}")]
+    OutputVerification(String),
+    #[error("Watch folder not found: {0}")]
+    WatchFolderNotFound(String),
+    #[error("Invalid output rule: {0}")]
+    InvalidOutputRule(String),
+    #[error("History entry not found: {0}")]
+    HistoryEntryNotFound(String),
+    #[error("No log found for job: {0}")]
+    LogNotFound(String),
+    #[error("Invalid settings: {0}")]
+    InvalidSettings(String),
+}
+
+impl Error {
+    // Process exit code of the command line interface, 2 is used for a wrong command line
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Io(_)
+            | Error::JobNotFound
+            | Error::WatchFolderNotFound(_)
+            | Error::HistoryEntryNotFound(_)
+            | Error::LogNotFound(_) => 1,
+            Error::NotAFile(_) | Error::NotAFolder(_) | Error::InvalidPath => 3,
+            Error::FfmpegFailed(failure) => failure.code,
+            Error::FfmpegStdout => 4,
+            Error::FfmpegNotInstalled | Error::InvalidFfmpeg(_) => 5,
+            Error::ImageError(_) => 6,
+            Error::InvalidCategory(_) => 7,
+            Error::PresetNotFound(_) | Error::PresetExists(_) | Error::InvalidPreset(_) => 8,
+            Error::ProbeFailed(_) => 9,
+            Error::OutputExists(_) => 10,
+            Error::OutputVerification(_) => 11,
+            Error::InvalidOutputRule(_) => 12,
+            Error::InvalidSettings(_) => 13,
+            // Same as a process stopped with Ctrl+C
+            Error::ConversionCancelled => 130,
+        }
+    }
 }
 
 impl From<std::io::Error> for Error {
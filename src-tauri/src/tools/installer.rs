@@ -1,12 +1,12 @@
 use futures_util::StreamExt;
 use reqwest::Client;
+use sha2::{Digest, Sha256};
 use std::fs;
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
-use std::{cmp::min, io::Write};
+use std::{cmp::min, io::Write, path::Path};
 #[cfg(unix)]
 use tar::Archive;
-use tauri::{Emitter, Manager};
 use thiserror::Error;
 #[cfg(unix)]
 use xz::read::XzDecoder;
@@ -22,6 +22,16 @@ pub enum FFmpegError {
     #[error("[FFmpeg Install] Archive Error: {0}")]
     ZipError(#[from] zip::result::ZipError),
 
+    #[error("[FFmpeg Install] Checksum mismatch for {file}: expected {expected}, got {actual}. The download is damaged or was tampered with, nothing was installed.")]
+    ChecksumMismatch {
+        file: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("[FFmpeg Install] No checksum is published for the FFmpeg build of this platform, so the download can't be verified. Install it anyway, or install FFmpeg yourself (e.g. with Homebrew) and choose it in the settings.")]
+    Unverified,
+
     #[error("[FFmpeg Install] Error: {0}")]
     Other(String),
 }
@@ -32,23 +42,18 @@ macro_rules! ffmpeg_err {
     };
 }
 
-use crate::tools::app_handle::app;
+use crate::{
+    helpers::{
+        discovery_helper::{inspect_ffmpeg, FfmpegOrigin},
+        ffmpeg_helper::{binary_name, bundled_ffmpeg_path, get_ffmpeg_path},
+    },
+    tools::engine::{data_path, emit},
+};
 
+// True if the FFmpeg jobs use exists, whether it was downloaded or chosen by the user
 #[tauri::command]
 pub fn is_ffmpeg_installed() -> bool {
-    app()
-        .path()
-        .app_local_data_dir()
-        .ok()
-        .map(|dir| {
-            let ffmpeg_path = if cfg!(target_os = "windows") {
-                dir.join("bin/ffmpeg.exe")
-            } else {
-                dir.join("bin/ffmpeg")
-            };
-            ffmpeg_path.exists()
-        })
-        .unwrap_or(false)
+    get_ffmpeg_path().is_ok_and(|ffmpeg_path| ffmpeg_path.exists())
 }
 
 fn ffmpeg_url() -> &'static str {
@@ -61,37 +66,120 @@ fn ffmpeg_url() -> &'static str {
     }
 }
 
+// BtbN publishes the SHA-256 of every archive of a release. evermeet only publishes GPG signatures,
+// so its archives are only installed when the user accepts that they can't be checked.
+fn checksums_url() -> Option<&'static str> {
+    if cfg!(target_os = "macos") {
+        None
+    } else {
+        Some("https://github.com/BtbN/FFmpeg-Builds/releases/download/latest/checksums.sha256")
+    }
+}
+
+// Looks up the hash of `file` in a sha256sum style list ("<hash>  <file>" per line)
+async fn expected_checksum(client: &Client, file: &str) -> Result<Option<String>, FFmpegError> {
+    let Some(url) = checksums_url() else {
+        return Ok(None);
+    };
+
+    let checksums = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    checksums
+        .lines()
+        .find_map(|line| {
+            let (hash, name) = line.split_once(char::is_whitespace)?;
+            (name.trim().trim_start_matches('*') == file).then(|| hash.to_lowercase())
+        })
+        .map(Some)
+        .ok_or_else(|| ffmpeg_err!(format!("No checksum is published for {}", file)))
+}
+
+// evermeet ships ffprobe as a separate archive, the other builds contain both binaries
+fn ffprobe_url() -> Option<&'static str> {
+    if cfg!(target_os = "macos") {
+        Some("https://evermeet.cx/ffmpeg/getrelease/ffprobe/zip")
+    } else {
+        None
+    }
+}
+
+// `allow_unverified` installs a build without published checksums, after the user was asked
 #[tauri::command]
-pub async fn install_ffmpeg() -> Result<(), String> {
-    match start_install_ffmpeg().await {
+pub async fn install_ffmpeg(allow_unverified: Option<bool>) -> Result<(), String> {
+    match start_install_ffmpeg(allow_unverified.unwrap_or(false)).await {
         Ok(_) => Ok(()),
         Err(e) => {
-            app().emit("ffmpeg-failed", e.to_string()).ok();
+            emit("ffmpeg-failed", e.to_string());
             Err(e.to_string())
         }
     }
 }
 
-pub async fn start_install_ffmpeg() -> Result<(), FFmpegError> {
+// Compares the hash of the download with the published one. BtbN replaces the "latest" release every day,
+// if that happened between fetching the checksums and the archive, the checksums are fetched again.
+async fn verify_checksum(
+    client: &Client,
+    archive_name: &str,
+    expected: &str,
+    actual: &str,
+) -> Result<(), FFmpegError> {
+    if actual == expected {
+        return Ok(());
+    }
+
+    let refreshed = expected_checksum(client, archive_name).await?;
+
+    if refreshed.as_deref() == Some(actual) {
+        return Ok(());
+    }
+
+    Err(FFmpegError::ChecksumMismatch {
+        file: archive_name.to_string(),
+        expected: refreshed.unwrap_or_else(|| expected.to_string()),
+        actual: actual.to_string(),
+    })
+}
+
+pub async fn start_install_ffmpeg(allow_unverified: bool) -> Result<(), FFmpegError> {
     let url = ffmpeg_url();
+    let archive_name = url.rsplit('/').next().unwrap_or(url);
 
     let client = Client::new();
-    let res = client.get(url).send().await?;
 
-    let total_size = res.content_length().unwrap();
+    // Fetched first, so a missing checksum doesn't cost a whole download
+    let expected = expected_checksum(&client, archive_name).await?;
+
+    if expected.is_none() {
+        if !allow_unverified {
+            emit("ffmpeg-install-state", "unverified");
+            return Err(FFmpegError::Unverified);
+        }
+
+        log::warn!(
+            "Installing {} without a checksum check, accepted by the user",
+            archive_name
+        );
+    }
+
+    let res = client.get(url).send().await?.error_for_status()?;
+
+    // Not every server sends the size, the progress is only reported when it is known
+    let total_size = res.content_length().filter(|size| *size > 0);
 
-    println!(
-        "Starting ffmpeg download from {}",
-        (total_size as f64 / (1024.0 * 1024.0))
+    log::info!(
+        "Starting ffmpeg download of {:.1} MB",
+        total_size.unwrap_or(0) as f64 / (1024.0 * 1024.0)
     );
 
-    app().emit("ffmpeg-install-state", "downloading").unwrap();
+    emit("ffmpeg-install-state", "downloading");
 
-    let ffmpeg_dir = app()
-        .path()
-        .app_local_data_dir()
-        .expect("Error resolving resource dir")
-        .join("bin");
+    let ffmpeg_dir = data_path("bin").map_err(|e| ffmpeg_err!(e))?;
     let down_path = if cfg!(target_os = "linux") {
         ffmpeg_dir.join("ffmpeg.tar.xz")
     } else {
@@ -99,45 +187,61 @@ pub async fn start_install_ffmpeg() -> Result<(), FFmpegError> {
     };
 
     if !ffmpeg_dir.exists() {
-        fs::create_dir_all(ffmpeg_dir).expect("error creating bin directory");
+        fs::create_dir_all(&ffmpeg_dir)?;
     }
 
-    let mut file = fs::File::create(down_path)?;
+    let mut file = fs::File::create(&down_path)?;
+    let mut hasher = Sha256::new();
     let mut downloaded: u64 = 0;
     let mut stream = res.bytes_stream();
 
     while let Some(item) = stream.next().await {
         let chunk = item?;
 
+        hasher.update(&chunk);
         file.write_all(&chunk)
             .map_err(|_| ffmpeg_err!("Error while downloading file."))?;
 
-        let new = min(downloaded + (chunk.len() as u64), total_size);
+        if let Some(total_size) = total_size {
+            let new = min(downloaded + (chunk.len() as u64), total_size);
 
-        downloaded = new;
+            downloaded = new;
 
-        let progress = ((downloaded as f64 / total_size as f64) * 100.0).min(100.0) as i32;
+            let progress = ((downloaded as f64 / total_size as f64) * 100.0).min(100.0) as i32;
 
-        app().emit("ffmpeg-install-progress", progress).unwrap()
+            emit("ffmpeg-install-progress", progress)
+        }
     }
 
-    if let Err(e) = setup_ffmpeg().await {
-        app()
-            .emit("ffmpeg-failed", format!("Failed to setup ffmpeg: {}", e))
-            .unwrap()
+    drop(file);
+
+    if let Some(expected) = expected {
+        let actual = format!("{:x}", hasher.finalize());
+
+        if let Err(e) = verify_checksum(&client, archive_name, &expected, &actual).await {
+            fs::remove_file(&down_path).ok();
+            return Err(e);
+        }
+
+        log::info!("Checksum of {} verified", archive_name);
     }
 
-    Ok(())
+    if let Some(ffprobe_url) = ffprobe_url() {
+        let res = client.get(ffprobe_url).send().await?.error_for_status()?;
+        let bytes = res.bytes().await?;
+
+        fs::write(ffmpeg_dir.join("ffprobe.zip"), bytes)?;
+    }
+
+    setup_ffmpeg()
+        .await
+        .map_err(|e| ffmpeg_err!(format!("Failed to setup ffmpeg: {}", e)))
 }
 
 pub async fn setup_ffmpeg() -> Result<(), FFmpegError> {
-    let ffmpeg_dir = app()
-        .path()
-        .app_local_data_dir()
-        .expect("Error resolving resource dir")
-        .join("bin");
+    let ffmpeg_dir = data_path("bin").map_err(|e| ffmpeg_err!(e))?;
 
-    app().emit("ffmpeg-install-state", "installing").unwrap();
+    emit("ffmpeg-install-state", "installing");
 
     #[cfg(windows)]
     {
@@ -145,7 +249,7 @@ pub async fn setup_ffmpeg() -> Result<(), FFmpegError> {
 
         let file = fs::File::open(archive)?;
 
-        let mut zip_archive = zip::ZipArchive::new(&file).expect("Error reading ffmpeg archive");
+        let mut zip_archive = zip::ZipArchive::new(&file)?;
 
         zip_archive.extract(&ffmpeg_dir)?;
 
@@ -161,19 +265,21 @@ pub async fn setup_ffmpeg() -> Result<(), FFmpegError> {
                 }
             })
             .ok_or_else(|| ffmpeg_err!("Could not find extracted ffmpeg folder"))?;
-        let unpacked = extracted_dir.join("bin").join("ffmpeg.exe");
-        let new_file = ffmpeg_dir.join("ffmpeg.exe");
+        for binary in ["ffmpeg.exe", "ffprobe.exe"] {
+            let unpacked = extracted_dir.join("bin").join(binary);
+            let new_file = ffmpeg_dir.join(binary);
 
-        fs::copy(unpacked, &new_file)?;
+            fs::copy(unpacked, &new_file)?;
+        }
     }
 
     #[cfg(unix)]
     {
-        let new_file = ffmpeg_dir.join("ffmpeg");
+        let binaries = [ffmpeg_dir.join("ffmpeg"), ffmpeg_dir.join("ffprobe")];
 
         if cfg!(target_os = "linux") {
             let archive = ffmpeg_dir.join("ffmpeg.tar.xz");
-            let archive_file = fs::File::open(archive).unwrap();
+            let archive_file = fs::File::open(archive)?;
 
             let tar = XzDecoder::new(archive_file);
             let mut tar_xz = Archive::new(tar);
@@ -182,38 +288,67 @@ pub async fn setup_ffmpeg() -> Result<(), FFmpegError> {
                 .unpack(&ffmpeg_dir)
                 .map_err(|_| ffmpeg_err!("Error decompressing ffmpeg archive"))?;
 
-            let unpacked = ffmpeg_dir
+            let unpacked_dir = ffmpeg_dir
                 .join("ffmpeg-master-latest-linux64-gpl")
-                .join("bin")
-                .join("ffmpeg");
-            fs::copy(unpacked, &new_file)?;
-        } else if cfg!(target_os = "macos") {
-            let archive = ffmpeg_dir.join("ffmpeg.zip");
+                .join("bin");
 
-            let file = fs::File::open(archive)?;
+            for (new_file, binary) in binaries.iter().zip(["ffmpeg", "ffprobe"]) {
+                fs::copy(unpacked_dir.join(binary), new_file)?;
+            }
+        } else if cfg!(target_os = "macos") {
+            for archive in ["ffmpeg.zip", "ffprobe.zip"] {
+                let file = fs::File::open(ffmpeg_dir.join(archive))?;
 
-            let mut zip_archive = zip::ZipArchive::new(&file)?;
+                let mut zip_archive = zip::ZipArchive::new(&file)?;
 
-            zip_archive.extract(&ffmpeg_dir)?;
+                zip_archive.extract(&ffmpeg_dir)?;
+            }
         }
 
-        let metadata = fs::metadata(&new_file)?;
-        let mut perms = metadata.permissions();
-        perms.set_mode(0o755); // executable permission
-        fs::set_permissions(&new_file, perms).unwrap();
+        for new_file in &binaries {
+            let metadata = fs::metadata(new_file)?;
+            let mut perms = metadata.permissions();
+            perms.set_mode(0o755); // executable permission
+            fs::set_permissions(new_file, perms)?;
+        }
     };
 
-    Ok(clean_installation())
+    let verified = verify_installation().await;
+
+    clean_installation(&ffmpeg_dir);
+
+    verified?;
+
+    emit("ffmpeg-install-state", "completed");
+
+    Ok(())
 }
 
-pub fn clean_installation() {
-    let ffmpeg_dir = app()
-        .path()
-        .app_local_data_dir()
-        .expect("Error resolving resource dir")
-        .join("bin");
+// Runs the installed FFmpeg, a binary that doesn't start (e.g. built for another architecture) is removed
+// so the app asks to install it again instead of failing every job
+async fn verify_installation() -> Result<(), FFmpegError> {
+    emit("ffmpeg-install-state", "verifying");
+
+    let ffmpeg_path = bundled_ffmpeg_path().map_err(|e| ffmpeg_err!(e))?;
+
+    match inspect_ffmpeg(&ffmpeg_path, FfmpegOrigin::Bundled).await {
+        Ok(build) => {
+            log::info!("Installed FFmpeg {}", build.version);
+            Ok(())
+        }
+        Err(e) => {
+            fs::remove_file(&ffmpeg_path).ok();
+            fs::remove_file(ffmpeg_path.with_file_name(binary_name("ffprobe"))).ok();
 
-    app().emit("ffmpeg-install-state", "cleaning").unwrap();
+            Err(ffmpeg_err!(e))
+        }
+    }
+}
+
+// Removes the downloaded archives and unpacked folders, a leftover file is logged instead of failing the
+// install since the binaries are already in place
+pub fn clean_installation(ffmpeg_dir: &Path) {
+    emit("ffmpeg-install-state", "cleaning");
 
     let ffmpeg_archive = if cfg!(target_os = "linux") {
         ffmpeg_dir.join("ffmpeg.tar.xz")
@@ -221,11 +356,19 @@ pub fn clean_installation() {
         ffmpeg_dir.join("ffmpeg.zip")
     };
 
-    fs::remove_file(ffmpeg_archive).unwrap();
+    let mut leftovers = vec![fs::remove_file(ffmpeg_archive)];
+    #[cfg(target_os = "macos")]
+    leftovers.push(fs::remove_file(ffmpeg_dir.join("ffprobe.zip")));
     #[cfg(target_os = "windows")]
-    fs::remove_dir_all(ffmpeg_dir.join("ffmpeg-master-latest-win64-gpl")).unwrap();
+    leftovers.push(fs::remove_dir_all(
+        ffmpeg_dir.join("ffmpeg-master-latest-win64-gpl"),
+    ));
     #[cfg(target_os = "linux")]
-    fs::remove_dir_all(ffmpeg_dir.join("ffmpeg-master-latest-linux64-gpl")).unwrap();
+    leftovers.push(fs::remove_dir_all(
+        ffmpeg_dir.join("ffmpeg-master-latest-linux64-gpl"),
+    ));
 
-    app().emit("ffmpeg-install-state", "completed").unwrap()
+    for e in leftovers.into_iter().filter_map(Result::err) {
+        log::warn!("Could not clean up the FFmpeg download: {}", e);
+    }
 }
@@ -0,0 +1,171 @@
+use std::{
+    io,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use tokio::sync::Notify;
+
+// Control handle shared between the pipeline and a running conversion.
+// The pipeline only flips the requested state, the conversion task owns the FFmpeg child and applies it.
+pub struct JobControl {
+    cancel: Notify,
+    state_changed: Notify,
+    paused: AtomicBool,
+}
+
+impl JobControl {
+    pub fn new() -> Self {
+        Self {
+            cancel: Notify::new(),
+            state_changed: Notify::new(),
+            paused: AtomicBool::new(false),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.notify_one();
+    }
+
+    pub async fn cancelled(&self) {
+        self.cancel.notified().await
+    }
+
+    // Returns false if the job was already in the requested state
+    pub fn set_paused(&self, paused: bool) -> bool {
+        let was_paused = self.paused.swap(paused, Ordering::SeqCst);
+
+        if was_paused != paused {
+            self.state_changed.notify_one();
+            return true;
+        }
+
+        false
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    pub async fn state_changed(&self) {
+        self.state_changed.notified().await
+    }
+}
+
+#[cfg(unix)]
+pub fn suspend_process(pid: u32) -> io::Result<()> {
+    send_signal(pid, libc::SIGSTOP)
+}
+
+#[cfg(unix)]
+pub fn resume_process(pid: u32) -> io::Result<()> {
+    send_signal(pid, libc::SIGCONT)
+}
+
+// Whether a process with this id is still running, used to tell files of a crashed session apart
+#[cfg(unix)]
+pub fn process_alive(pid: u32) -> bool {
+    // SAFETY: signal 0 only checks that the process exists, nothing is sent
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+
+    // EPERM means it exists but belongs to another user
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(windows)]
+pub fn process_alive(pid: u32) -> bool {
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, STILL_ACTIVE},
+        System::Threading::{GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION},
+    };
+
+    // SAFETY: the handle is checked before use and closed before returning
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+
+        if process.is_null() {
+            return false;
+        }
+
+        let mut code = 0u32;
+        let queried = GetExitCodeProcess(process, &mut code) != 0;
+
+        CloseHandle(process);
+
+        queried && code == STILL_ACTIVE as u32
+    }
+}
+
+#[cfg(unix)]
+fn send_signal(pid: u32, signal: libc::c_int) -> io::Result<()> {
+    // SAFETY: kill has no memory safety requirements, pid belongs to our own FFmpeg child
+    let result = unsafe { libc::kill(pid as libc::pid_t, signal) };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(windows)]
+pub fn suspend_process(pid: u32) -> io::Result<()> {
+    for_each_thread(pid, true)
+}
+
+#[cfg(windows)]
+pub fn resume_process(pid: u32) -> io::Result<()> {
+    for_each_thread(pid, false)
+}
+
+// Windows has no public API to suspend a whole process, so every thread of it is suspended one by one
+#[cfg(windows)]
+fn for_each_thread(pid: u32, suspend: bool) -> io::Result<()> {
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, INVALID_HANDLE_VALUE},
+        System::{
+            Diagnostics::ToolHelp::{
+                CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD,
+                THREADENTRY32,
+            },
+            Threading::{OpenThread, ResumeThread, SuspendThread, THREAD_SUSPEND_RESUME},
+        },
+    };
+
+    // SAFETY: handles are checked before use and closed before returning
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0);
+
+        if snapshot == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut entry: THREADENTRY32 = std::mem::zeroed();
+        entry.dwSize = std::mem::size_of::<THREADENTRY32>() as u32;
+
+        if Thread32First(snapshot, &mut entry) != 0 {
+            loop {
+                if entry.th32OwnerProcessID == pid {
+                    let thread = OpenThread(THREAD_SUSPEND_RESUME, 0, entry.th32ThreadID);
+
+                    if !thread.is_null() {
+                        if suspend {
+                            SuspendThread(thread);
+                        } else {
+                            ResumeThread(thread);
+                        }
+
+                        CloseHandle(thread);
+                    }
+                }
+
+                if Thread32Next(snapshot, &mut entry) == 0 {
+                    break;
+                }
+            }
+        }
+
+        CloseHandle(snapshot);
+    }
+
+    Ok(())
+}
@@ -1,5 +1,7 @@
 pub mod app_handle;
 pub mod convert;
+pub mod engine;
 pub mod error;
 pub mod installer;
+pub mod job_control;
 pub mod stopwatch;
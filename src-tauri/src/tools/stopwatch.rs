@@ -19,13 +19,13 @@ impl Stopwatch {
         }
     }
 
-    // WILL BE USED WHEN MULTIPLE CONVERSION ADDED
-    /* pub fn stop(&mut self) {
+    // Used while a job is paused, start() continues counting from the elapsed time
+    pub fn stop(&mut self) {
         if let Some(start) = self.start_time {
             self.elapsed += start.elapsed();
             self.start_time = None
         }
-    } */
+    }
 
     pub fn reset(&mut self) {
         self.start_time = None;